@@ -1,3 +1,19 @@
+pub mod annealing;
+pub mod ant_colony;
 pub mod genetic;
+pub mod local_search;
+pub mod magic_rules;
+pub mod nsga2;
+pub mod operators;
+pub mod optimizer;
+pub mod romaji_rules;
 
+pub use annealing::*;
+pub use ant_colony::*;
 pub use genetic::*;
+pub use local_search::*;
+pub use magic_rules::*;
+pub use nsga2::*;
+pub use operators::{Adaptation, Crossover, Mutation, Operators};
+pub use optimizer::*;
+pub use romaji_rules::*;
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use super::local_search::SwapScorer;
+use super::optimizer::{OptimizeResult, Optimizer, RunOptions, StopReason, TopLayouts};
+use crate::keyboard_layout::{LogicalLayout, PhysicalLayout};
+use crate::n_gram::LogicalNGram;
+
+/// How the temperature changes after every iteration of simulated annealing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Multiplies the temperature by `alpha`.
+    Geometric { alpha: f32 },
+    /// Cools by `alpha` while more moves than `target_acceptance` are accepted and heats by
+    /// `alpha` otherwise.
+    Adaptive { alpha: f32, target_acceptance: f32 },
+    /// Cools by `alpha` and restarts at the initial temperature after `stall` iterations without
+    /// improvement.
+    Reheating { alpha: f32, stall: usize },
+}
+
+impl Schedule {
+    fn next(&self, temperature: f32, initial: f32, acceptance: f32, stalled: usize) -> f32 {
+        match *self {
+            Schedule::Geometric { alpha } => temperature * alpha,
+            Schedule::Adaptive {
+                alpha,
+                target_acceptance,
+            } => {
+                if acceptance > target_acceptance {
+                    temperature * alpha
+                } else {
+                    (temperature / alpha).min(initial)
+                }
+            }
+            Schedule::Reheating { alpha, stall } => {
+                if stalled > 0 && stalled.is_multiple_of(stall) {
+                    initial
+                } else {
+                    temperature * alpha
+                }
+            }
+        }
+    }
+}
+
+/// Simulated annealing over swaps of two slots of a layout.
+pub struct SimulatedAnnealing {
+    initial_temperature: f32,
+    /// Swaps tried in every iteration.
+    moves_per_iteration: usize,
+    schedule: Schedule,
+}
+
+impl SimulatedAnnealing {
+    pub fn new(initial_temperature: f32, moves_per_iteration: usize, schedule: Schedule) -> Self {
+        if initial_temperature <= 0.0 {
+            panic!("initial_temperature must be positive");
+        }
+        Self {
+            initial_temperature,
+            moves_per_iteration,
+            schedule,
+        }
+    }
+}
+
+/// The state of a run of simulated annealing.
+struct Annealing<'a> {
+    scorer: SwapScorer<'a>,
+    current: LogicalLayout,
+    current_score: f32,
+    best: LogicalLayout,
+    best_score: f32,
+    temperature: f32,
+    /// Iterations since the best layout last improved.
+    stalled: usize,
+}
+
+impl SimulatedAnnealing {
+    fn start<'a>(&self, layout: LogicalLayout, scorer: SwapScorer<'a>) -> Annealing<'a> {
+        let score = scorer.score(&layout);
+        Annealing {
+            scorer,
+            best: layout.clone(),
+            current: layout,
+            current_score: score,
+            best_score: score,
+            temperature: self.initial_temperature,
+            stalled: 0,
+        }
+    }
+
+    /// Tries `moves_per_iteration` swaps at the current temperature, then moves on to the
+    /// temperature of the next iteration.
+    fn iterate(&self, state: &mut Annealing, rng: &mut fastrand::Rng) {
+        let n = state.current.len();
+        let mut accepted = 0;
+        for _ in 0..self.moves_per_iteration {
+            let a = rng.usize(0..n);
+            let b = rng.usize(0..n);
+            if a == b
+                || state.current.get(a) == state.current.get(b)
+                || !state.current.can_swap(a, b)
+            {
+                continue;
+            }
+            let delta = state.scorer.delta(&mut state.current, a, b);
+            if delta <= 0.0 || rng.f32() < (-delta / state.temperature).exp() {
+                state.current.swap(a, b);
+                state.current_score += delta;
+                accepted += 1;
+                if state.current_score < state.best_score {
+                    state.best = state.current.clone();
+                    state.best_score = state.current_score;
+                    state.stalled = 0;
+                }
+            }
+        }
+
+        // keep the rounding errors of the deltas from adding up
+        if state.stalled == 0 {
+            state.best_score = state.scorer.score(&state.best);
+        }
+        state.current_score = if state.current.slots() == state.best.slots() {
+            state.best_score
+        } else {
+            state.scorer.score(&state.current)
+        };
+        let acceptance = accepted as f32 / self.moves_per_iteration.max(1) as f32;
+        state.temperature = self
+            .schedule
+            .next(
+                state.temperature,
+                self.initial_temperature,
+                acceptance,
+                state.stalled + 1,
+            )
+            .max(f32::MIN_POSITIVE);
+        state.stalled += 1;
+    }
+}
+
+impl Optimizer for SimulatedAnnealing {
+    fn run(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        options: &RunOptions,
+    ) -> OptimizeResult {
+        let mut rng = fastrand::Rng::new();
+        let mut layout = initial_layout.clone();
+        if options.shuffle {
+            layout.shuffle(&mut rng);
+        }
+        let scorer = SwapScorer::new(&layout, physical_layout, tri_grams);
+        let mut state = self.start(layout, scorer);
+        let mut top_layouts = TopLayouts::new(options.top_k);
+        top_layouts.insert(&state.current, state.current_score);
+
+        let mut history = Vec::with_capacity(options.iterations);
+        let mut stop_reason = StopReason::Iterations;
+        for i in 0..options.iterations {
+            self.iterate(&mut state, &mut rng);
+            top_layouts.insert(&state.current, state.current_score);
+            history.push(state.best_score);
+
+            if i % 100 == 0 {
+                println!("iteration: {} / {}", i, options.iterations);
+                println!(
+                    "best score: {}, temperature: {}",
+                    state.best_score, state.temperature
+                );
+            }
+
+            if state.stalled > options.early_stop_count {
+                println!(
+                    "No improvement for {} iterations, stopping...",
+                    options.early_stop_count
+                );
+                stop_reason = StopReason::NoImprovement;
+                break;
+            }
+        }
+
+        top_layouts.insert(&state.best, state.best_score);
+        OptimizeResult {
+            layout: state.best,
+            score: state.best_score,
+            iterations: history.len(),
+            stop_reason,
+            history,
+            top_layouts: top_layouts.into_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::{sample_physical_layout, Finger, Shift};
+
+    #[test]
+    fn test_simulated_annealing() {
+        let geometric = Schedule::Geometric { alpha: 0.5 };
+        assert_eq!(geometric.next(2.0, 2.0, 0.0, 1), 1.0);
+        let adaptive = Schedule::Adaptive {
+            alpha: 0.5,
+            target_acceptance: 0.3,
+        };
+        assert_eq!(adaptive.next(1.0, 2.0, 0.5, 1), 0.5);
+        assert_eq!(adaptive.next(1.0, 2.0, 0.1, 1), 2.0);
+        let reheating = Schedule::Reheating {
+            alpha: 0.5,
+            stall: 3,
+        };
+        assert_eq!(reheating.next(1.0, 2.0, 0.0, 2), 0.5);
+        assert_eq!(reheating.next(1.0, 2.0, 0.0, 3), 2.0);
+
+        let mut physical_layout = sample_physical_layout();
+        let shift = physical_layout.add_key((2, 0), Finger::P, 3.0);
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefghij".chars().collect();
+        let mut initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        initial_layout.set_shift(Shift::us(shift));
+        // the cost of the shifted 'B' follows the slot of 'b'
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+            (LogicalNGram::new(['g', 'h', 'i']), 0.2),
+            (LogicalNGram::new(['i', 'j', 'B']), 0.2),
+        ]
+        .into_iter()
+        .collect();
+        let initial_score = initial_layout.evaluate(&physical_layout, &tri_grams);
+
+        let annealing = SimulatedAnnealing::new(1.0, 30, reheating);
+        let options = RunOptions {
+            iterations: 20,
+            shuffle: false,
+            early_stop_count: 20,
+            top_k: 3,
+        };
+        let result = annealing.run(&physical_layout, &initial_layout, &tri_grams, &options);
+        assert!(result.score <= initial_score);
+        assert_eq!(result.top_layouts[0].1, result.score);
+        let evaluated = result.layout.evaluate(&physical_layout, &tri_grams);
+        assert!((evaluated - result.score).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_reheat() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefgh".chars().collect();
+        let layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        // without tri-grams no swap improves the layout, so every iteration stalls
+        let tri_grams = HashMap::new();
+        let scorer = SwapScorer::new(&layout, &physical_layout, &tri_grams);
+        let schedule = Schedule::Reheating {
+            alpha: 0.5,
+            stall: 3,
+        };
+        let annealing = SimulatedAnnealing::new(2.0, 10, schedule);
+        let mut state = annealing.start(layout, scorer);
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut temperatures = Vec::new();
+        for _ in 0..6 {
+            annealing.iterate(&mut state, &mut rng);
+            temperatures.push(state.temperature);
+        }
+        assert_eq!(temperatures, [1.0, 0.5, 2.0, 1.0, 0.5, 2.0]);
+        assert_eq!(state.stalled, 6);
+    }
+}
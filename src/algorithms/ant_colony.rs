@@ -0,0 +1,284 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+use super::optimizer::{OptimizeResult, Optimizer, RunOptions, StopReason, TopLayouts};
+use crate::keyboard_layout::{LogicalLayout, PhysicalLayout};
+use crate::n_gram::LogicalNGram;
+
+/// MAX-MIN ant system over pheromones of (char, slot) pairs.
+///
+/// Every ant places the chars one by one on a free slot drawn in proportion to the pheromone of
+/// the pair. Only the best ant of an iteration deposits pheromone, and every pheromone is kept
+/// between bounds derived from the best score so far, so no placement is ever ruled out.
+pub struct AntColony {
+    ants: usize,
+    /// Fraction of the pheromone evaporating in every iteration.
+    evaporation: f32,
+}
+
+impl AntColony {
+    pub fn new(ants: usize, evaporation: f32) -> Self {
+        if ants == 0 {
+            panic!("ants must be greater than 0");
+        }
+        if evaporation <= 0.0 || evaporation > 1.0 {
+            panic!("evaporation must be in (0, 1]");
+        }
+        Self { ants, evaporation }
+    }
+
+    fn pheromone_bounds(&self, best_score: f32, slots: usize) -> (f32, f32) {
+        let max = 1.0 / (self.evaporation * best_score.max(f32::EPSILON));
+        (max / (2 * slots) as f32, max)
+    }
+
+    /// Evaporates every pheromone, lets `deposit` add `1 / score` to the pairs of its layout and
+    /// keeps every pheromone within `bounds`.
+    fn update_pheromone(
+        &self,
+        pheromone: &mut [Vec<f32>],
+        rows: &HashMap<char, usize>,
+        deposit: Option<(&LogicalLayout, f32)>,
+        (tau_min, tau_max): (f32, f32),
+    ) {
+        for row in pheromone.iter_mut() {
+            for tau in row.iter_mut() {
+                *tau *= 1.0 - self.evaporation;
+            }
+        }
+        if let Some((layout, score)) = deposit {
+            let amount = 1.0 / score.max(f32::EPSILON);
+            for (slot, c) in layout.slots().iter().enumerate() {
+                if let Some(c) = c {
+                    pheromone[rows[c]][slot] += amount;
+                }
+            }
+        }
+        for row in pheromone.iter_mut() {
+            for tau in row.iter_mut() {
+                *tau = tau.clamp(tau_min, tau_max);
+            }
+        }
+    }
+
+    /// Builds a layout from the pheromones, placing the most constrained chars first. Returns
+    /// `None` when a char has no free allowed slot left.
+    fn construct(
+        template: &LogicalLayout,
+        chars: &[char],
+        allowed_counts: &[usize],
+        pheromone: &[Vec<f32>],
+        rng: &mut fastrand::Rng,
+    ) -> Option<LogicalLayout> {
+        let n = template.len();
+        let mut layout = template.clone();
+        for slot in 0..n {
+            layout.set(slot, None);
+        }
+
+        let mut order: Vec<usize> = (0..chars.len()).collect();
+        rng.shuffle(&mut order);
+        order.sort_by_key(|i| allowed_counts[*i]);
+        let mut free = vec![true; n];
+        for i in order {
+            let candidates: Vec<usize> = (0..n)
+                .filter(|slot| free[*slot] && layout.allows(*slot, Some(chars[i])))
+                .collect();
+            let total: f32 = candidates.iter().map(|slot| pheromone[i][*slot]).sum();
+            let mut threshold = rng.f32() * total;
+            let slot = *candidates
+                .iter()
+                .find(|slot| {
+                    threshold -= pheromone[i][**slot];
+                    threshold <= 0.0
+                })
+                .or(candidates.last())?;
+            free[slot] = false;
+            layout.set(slot, Some(chars[i]));
+        }
+        Some(layout)
+    }
+}
+
+impl Optimizer for AntColony {
+    fn run(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        options: &RunOptions,
+    ) -> OptimizeResult {
+        let mut rng = fastrand::Rng::new();
+        let mut best = initial_layout.clone();
+        if options.shuffle {
+            best.shuffle(&mut rng);
+        }
+        let mut best_score = best.evaluate(physical_layout, tri_grams);
+        let mut top_layouts = TopLayouts::new(options.top_k);
+        top_layouts.insert(&best, best_score);
+
+        let n = best.len();
+        let chars: Vec<char> = best.chars().collect();
+        let rows: HashMap<char, usize> = chars.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+        let allowed_counts: Vec<usize> = chars
+            .iter()
+            .map(|c| (0..n).filter(|slot| best.allows(*slot, Some(*c))).count())
+            .collect();
+        let (mut tau_min, mut tau_max) = self.pheromone_bounds(best_score, n);
+        let mut pheromone = vec![vec![tau_max; n]; chars.len()];
+
+        let mut history = Vec::with_capacity(options.iterations);
+        let mut stop_reason = StopReason::Iterations;
+        let mut count = 0;
+        for i in 0..options.iterations {
+            let ants: Vec<LogicalLayout> = (0..self.ants)
+                .filter_map(|_| {
+                    Self::construct(&best, &chars, &allowed_counts, &pheromone, &mut rng)
+                })
+                .collect();
+            let scored: Vec<(LogicalLayout, f32)> = ants
+                .into_par_iter()
+                .map(|layout| {
+                    let score = layout.evaluate(physical_layout, tri_grams);
+                    (layout, score)
+                })
+                .collect();
+            for (layout, score) in &scored {
+                top_layouts.insert(layout, *score);
+            }
+
+            // Evaporate, then let the best ant of the iteration deposit
+            let iteration_best = scored
+                .into_iter()
+                .min_by(|x, y| x.1.partial_cmp(&y.1).expect("Failed to compare scores"));
+            let mut improved = false;
+            if let Some((_, score)) = &iteration_best {
+                if *score < best_score {
+                    improved = true;
+                    best_score = *score;
+                    count = 0;
+                    (tau_min, tau_max) = self.pheromone_bounds(best_score, n);
+                }
+            }
+            self.update_pheromone(
+                &mut pheromone,
+                &rows,
+                iteration_best
+                    .as_ref()
+                    .map(|(layout, score)| (layout, *score)),
+                (tau_min, tau_max),
+            );
+            if let Some((layout, _)) = iteration_best.filter(|_| improved) {
+                best = layout;
+            }
+            history.push(best_score);
+
+            if i % 100 == 0 {
+                println!("iteration: {} / {}", i, options.iterations);
+                println!("best score: {}", best_score);
+            }
+
+            count += 1;
+            if count > options.early_stop_count {
+                println!(
+                    "No improvement for {} iterations, stopping...",
+                    options.early_stop_count
+                );
+                stop_reason = StopReason::NoImprovement;
+                break;
+            }
+        }
+
+        OptimizeResult {
+            layout: best,
+            score: best_score,
+            iterations: history.len(),
+            stop_reason,
+            history,
+            top_layouts: top_layouts.into_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::{sample_physical_layout, Constraints};
+
+    #[test]
+    fn test_ant_colony() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefghij".chars().collect();
+        let mut initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let mut constraints = Constraints::new();
+        constraints.pin('j', 9).allow('a', [10, 11, 12, 13]);
+        initial_layout.set_constraints(&constraints);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+            (LogicalNGram::new(['g', 'h', 'i']), 0.2),
+        ]
+        .into_iter()
+        .collect();
+        let initial_score = initial_layout.evaluate(&physical_layout, &tri_grams);
+
+        let ant_colony = AntColony::new(5, 0.2);
+        let options = RunOptions {
+            iterations: 10,
+            shuffle: false,
+            early_stop_count: 10,
+            top_k: 3,
+        };
+        let result = ant_colony.run(&physical_layout, &initial_layout, &tri_grams, &options);
+        assert!(result.score <= initial_score);
+        assert_eq!(result.top_layouts[0].1, result.score);
+        let evaluated = result.layout.evaluate(&physical_layout, &tri_grams);
+        assert!((evaluated - result.score).abs() < 1e-4);
+        assert!(result
+            .top_layouts
+            .iter()
+            .all(|(layout, _)| layout.is_valid() && layout.chars().count() == 10));
+        assert_eq!(result.layout.get(9), Some('j'));
+    }
+
+    #[test]
+    fn test_pheromone_bounds() {
+        let physical_layout = sample_physical_layout();
+        let chars: Vec<char> = "abcd".chars().collect();
+        let layout = LogicalLayout::from_usable_chars(&physical_layout, chars.clone());
+        let rows: HashMap<char, usize> = chars.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+        let n = layout.len();
+
+        let ant_colony = AntColony::new(1, 0.5);
+        let (tau_min, tau_max) = ant_colony.pheromone_bounds(1.0, n);
+        assert!(0.0 < tau_min && tau_min < tau_max);
+        let mut pheromone = vec![vec![tau_max; n]; chars.len()];
+        // a deposit far above tau_max, repeated until the other pairs have evaporated
+        for _ in 0..20 {
+            ant_colony.update_pheromone(
+                &mut pheromone,
+                &rows,
+                Some((&layout, 0.01)),
+                (tau_min, tau_max),
+            );
+        }
+        for (c, row) in chars.iter().zip(&pheromone) {
+            for (slot, tau) in row.iter().enumerate() {
+                let expected = if layout.get(slot) == Some(*c) {
+                    tau_max
+                } else {
+                    tau_min
+                };
+                assert_eq!(*tau, expected);
+            }
+        }
+
+        // without a deposit, every pheromone evaporates down to tau_min and stays there
+        for _ in 0..20 {
+            ant_colony.update_pheromone(&mut pheromone, &rows, None, (tau_min, tau_max));
+        }
+        assert!(pheromone.iter().flatten().all(|tau| *tau == tau_min));
+    }
+}
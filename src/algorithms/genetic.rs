@@ -1,17 +1,263 @@
 use fastrand;
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
-use rand::seq::IteratorRandom;
 use rand::thread_rng;
 use rayon::prelude::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
+use super::local_search::{refine, SwapScorer};
+use super::operators::{
+    crossover, mutate, Choice, Crossover, Mutation, OperatorSelector, Operators,
+};
+use super::optimizer::{OptimizeResult, Optimizer, RunOptions, StopReason, TopLayouts};
 use crate::keyboard_layout::{LogicalLayout, PhysicalLayout};
 use crate::n_gram::{LogicalNGram, NGramDB};
 
+/// The individuals refined by the local search of the memetic mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefineTarget {
+    /// The elite of every island.
+    Elite,
+    /// Every child, before it enters the population.
+    Offspring,
+}
+
+/// Local refinement of individuals with a bounded first-improvement swap search.
+#[derive(Debug, Clone, Copy)]
+pub struct Memetic {
+    pub target: RefineTarget,
+    /// Refine every `interval` generations.
+    pub interval: usize,
+    /// Maximum number of improving swaps per refinement.
+    pub depth: usize,
+    /// Random swaps tried to find each improving swap.
+    pub samples: usize,
+}
+
+impl Memetic {
+    /// Refines the targeted individuals of a population whose first `elite_num` are the elites.
+    fn refine(&self, population: &mut [Individual], elite_num: usize, scorer: &SwapScorer) {
+        let refined = match self.target {
+            RefineTarget::Elite => &mut population[..elite_num],
+            RefineTarget::Offspring => &mut population[elite_num..],
+        };
+        refined.par_iter_mut().for_each(|individual| {
+            individual.score = refine(
+                &mut individual.layout,
+                individual.score,
+                scorer,
+                self.depth,
+                self.samples,
+                &mut fastrand::Rng::new(),
+            );
+        });
+    }
+}
+
+/// How parents are drawn from a population; every strategy favors lower scores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    /// The best of `size` individuals drawn uniformly.
+    Tournament { size: usize },
+    /// Roulette on linear ranks, where `pressure` in `1.0..=2.0` is the expected number of picks
+    /// of the best individual per population size picks.
+    Rank { pressure: f32 },
+    /// Roulette weighted by the inverse of the score.
+    InverseCost,
+    /// Uniformly among the best `fraction` of the population.
+    Truncation { fraction: f32 },
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Selection::Tournament { size: 2 }
+    }
+}
+
+impl Selection {
+    /// The roulette weights of `scores`, or `None` for tournament selection.
+    fn weights(&self, scores: &[f32]) -> Option<Vec<f32>> {
+        let n = scores.len();
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|a, b| {
+            scores[*a]
+                .partial_cmp(&scores[*b])
+                .expect("Failed to compare scores")
+        });
+        let mut weights = vec![0.0; n];
+        match *self {
+            Selection::Tournament { .. } => return None,
+            Selection::Rank { pressure } => {
+                for (rank, i) in order.iter().enumerate() {
+                    let share = (n - 1 - rank) as f32 / (n - 1).max(1) as f32;
+                    weights[*i] = 2.0 - pressure + 2.0 * (pressure - 1.0) * share;
+                }
+            }
+            Selection::InverseCost => {
+                for (weight, score) in weights.iter_mut().zip(scores) {
+                    *weight = 1.0 / score.max(f32::EPSILON);
+                }
+            }
+            Selection::Truncation { fraction } => {
+                let kept = ((n as f32 * fraction).ceil() as usize).clamp(1, n);
+                for i in &order[..kept] {
+                    weights[*i] = 1.0;
+                }
+            }
+        }
+        // a lone individual gets no rank weight at the maximum pressure
+        if weights.iter().all(|weight| *weight <= 0.0) {
+            weights.fill(1.0);
+        }
+        Some(weights)
+    }
+
+    /// Draws the index of a parent, with `dist` built from `weights` for roulette strategies.
+    fn sample(
+        &self,
+        scores: &[f32],
+        dist: Option<&WeightedIndex<f32>>,
+        rng: &mut ThreadRng,
+    ) -> usize {
+        match (self, dist) {
+            (_, Some(dist)) => dist.sample(rng),
+            (Selection::Tournament { size }, None) => (0..(*size).max(1))
+                .map(|_| rng.gen_range(0..scores.len()))
+                .min_by(|a, b| {
+                    scores[*a]
+                        .partial_cmp(&scores[*b])
+                        .expect("Failed to compare scores")
+                })
+                .expect("Failed to draw a tournament"),
+            (_, None) => rng.gen_range(0..scores.len()),
+        }
+    }
+}
+
+/// The islands receiving the migrants of an island.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// The next island.
+    Ring,
+    /// Every other island.
+    FullyConnected,
+    /// Another island drawn at every migration.
+    Random,
+    /// The four neighbors on a wrapping grid `width` islands wide, which must divide the number
+    /// of islands.
+    Torus { width: usize },
+}
+
+impl Topology {
+    fn targets(&self, island: usize, num_islands: usize, rng: &mut fastrand::Rng) -> Vec<usize> {
+        if num_islands < 2 {
+            return Vec::new();
+        }
+        let mut targets = match *self {
+            Topology::Ring => vec![(island + 1) % num_islands],
+            Topology::FullyConnected => (0..num_islands).collect(),
+            Topology::Random => vec![(island + rng.usize(1..num_islands)) % num_islands],
+            Topology::Torus { width } => {
+                let height = num_islands / width;
+                let (row, col) = (island / width, island % width);
+                vec![
+                    row * width + (col + 1) % width,
+                    row * width + (col + width - 1) % width,
+                    (row + 1) % height * width + col,
+                    (row + height - 1) % height * width + col,
+                ]
+            }
+        };
+        targets.retain(|target| *target != island);
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+}
+
+/// The individuals replaced by migrants; elites are never replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    Worst,
+    Random,
+    /// The worst individuals, only by better migrants.
+    IfBetter,
+}
+
+/// How the best individuals of the islands move between them.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub topology: Topology,
+    /// Migrate every `interval` generations.
+    pub interval: usize,
+    /// Number of best individuals sent to every target island.
+    pub migrants: usize,
+    pub replacement: Replacement,
+}
+
+impl Default for Migration {
+    fn default() -> Self {
+        Self {
+            topology: Topology::Ring,
+            interval: 10,
+            migrants: 1,
+            replacement: Replacement::Worst,
+        }
+    }
+}
+
+impl Migration {
+    /// Sends the best individuals of every sorted island to its targets and sorts the islands
+    /// again.
+    fn migrate(&self, islands: &mut [Vec<Individual>], elite_num: usize, rng: &mut fastrand::Rng) {
+        let emigrants: Vec<Vec<Individual>> = islands
+            .iter()
+            .map(|population| population.iter().take(self.migrants).cloned().collect())
+            .collect();
+        // the number of worst individuals already replaced on every island
+        let mut replaced = vec![0; islands.len()];
+        for (source, emigrants) in emigrants.into_iter().enumerate() {
+            for target in self.topology.targets(source, islands.len(), rng) {
+                let population = &mut islands[target];
+                for migrant in &emigrants {
+                    if population.len() <= elite_num {
+                        break;
+                    }
+                    let worst = population.len() - 1 - replaced[target];
+                    let index = match self.replacement {
+                        Replacement::Random => rng.usize(elite_num..population.len()),
+                        Replacement::Worst if worst >= elite_num => worst,
+                        Replacement::IfBetter
+                            if worst >= elite_num && migrant.score < population[worst].score =>
+                        {
+                            worst
+                        }
+                        _ => continue,
+                    };
+                    if index == worst {
+                        replaced[target] += 1;
+                    }
+                    population[index] = migrant.clone();
+                }
+            }
+        }
+        for population in islands.iter_mut() {
+            population.sort_by(|a, b| {
+                a.score
+                    .partial_cmp(&b.score)
+                    .expect("Failed to compare scores")
+            });
+        }
+    }
+}
+
 pub struct Genetic {
     population_size: usize,
     island_size: usize,
+    memetic: Option<Memetic>,
+    selection: Selection,
+    operators: Operators,
+    migration: Migration,
 }
 
 impl Genetic {
@@ -22,9 +268,59 @@ impl Genetic {
         Self {
             population_size,
             island_size,
+            memetic: None,
+            selection: Selection::default(),
+            operators: Operators::default(),
+            migration: Migration::default(),
         }
     }
 
+    pub fn with_migration(mut self, migration: Migration) -> Self {
+        if migration.interval == 0 {
+            panic!("migration interval must be greater than 0");
+        }
+        if let Topology::Torus { width } = migration.topology {
+            // a partial last row would leave some islands without all their neighbors
+            if width == 0 || !self.island_size.is_multiple_of(width) {
+                panic!("torus width must divide the number of islands");
+            }
+        }
+        self.migration = migration;
+        self
+    }
+
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        match selection {
+            Selection::Tournament { size: 0 } => {
+                panic!("tournament size must be greater than 0")
+            }
+            Selection::Rank { pressure } if !(1.0..=2.0).contains(&pressure) => {
+                panic!("rank pressure must be in [1, 2]")
+            }
+            Selection::Truncation { fraction } if fraction <= 0.0 || fraction > 1.0 => {
+                panic!("truncation fraction must be in (0, 1]")
+            }
+            _ => (),
+        }
+        self.selection = selection;
+        self
+    }
+
+    pub fn with_operators(mut self, operators: Operators) -> Self {
+        operators.validate();
+        self.operators = operators;
+        self
+    }
+
+    /// Refines individuals with a local search, which makes the search memetic.
+    pub fn with_memetic(mut self, memetic: Memetic) -> Self {
+        if memetic.interval == 0 {
+            panic!("memetic interval must be greater than 0");
+        }
+        self.memetic = Some(memetic);
+        self
+    }
+
     pub fn optimize(
         &self,
         physical_layout: &PhysicalLayout,
@@ -33,90 +329,169 @@ impl Genetic {
         iterations: usize,
         shuffle: bool,
         early_stop_count: usize,
-    ) {
+    ) -> OptimizeResult {
         let initial_layout =
             LogicalLayout::from_usable_chars(physical_layout, usable_chars.to_vec());
-        let mut layout = initial_layout.clone().output();
-        let mut best_layout = Individual::new(initial_layout);
-        let usable_chars_set: HashSet<char> = usable_chars.iter().cloned().collect();
+        self.optimize_layout(
+            physical_layout,
+            &initial_layout,
+            ngram_db,
+            iterations,
+            shuffle,
+            early_stop_count,
+        )
+    }
+
+    /// Optimizes the chars of a layout with layers, for the tri-grams it can type.
+    pub fn optimize_layout(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        ngram_db: &NGramDB,
+        iterations: usize,
+        shuffle: bool,
+        early_stop_count: usize,
+    ) -> OptimizeResult {
+        // every candidate types the same chars, so off-layout tri-grams would only add a constant
+        let usable_chars_set = initial_layout.typeable_chars();
         let tri_grams = ngram_db
             .get_tri_grams(&usable_chars_set)
             .expect("Failed to get tri grams");
-        best_layout.evaluate(physical_layout, &tri_grams);
+        let options = RunOptions {
+            iterations,
+            shuffle,
+            early_stop_count,
+            ..Default::default()
+        };
+        let result = self.run(physical_layout, initial_layout, &tri_grams, &options);
+
+        result.report(physical_layout, ngram_db);
+        result
+    }
+}
+
+impl Optimizer for Genetic {
+    fn run(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        options: &RunOptions,
+    ) -> OptimizeResult {
+        let mut layout = initial_layout.clone();
+        let mut best_layout = Individual::new(initial_layout.clone());
+        best_layout.evaluate(physical_layout, tri_grams);
+        let mut rng = fastrand::Rng::new();
+        let scorer = self
+            .memetic
+            .map(|_| SwapScorer::new(initial_layout, physical_layout, tri_grams));
 
         // initialize
         let mut islands = Vec::with_capacity(self.island_size);
         for _ in 0..self.island_size {
             let mut population = Vec::with_capacity(self.population_size);
             for _ in 0..self.population_size {
-                if shuffle {
-                    fastrand::shuffle(&mut layout);
+                if options.shuffle {
+                    layout.shuffle(&mut rng);
                 }
-                let copy = LogicalLayout::from_usable_chars(physical_layout, layout.clone());
-                let mut individual = Individual::new(copy);
-                individual.evaluate(physical_layout, &tri_grams);
+                let mut individual = Individual::new(layout.clone());
+                individual.evaluate(physical_layout, tri_grams);
                 population.push(individual);
             }
+            population.sort_by(|a, b| {
+                a.score
+                    .partial_cmp(&b.score)
+                    .expect("Failed to compare scores")
+            });
             islands.push(population);
         }
 
-        let elite_num = if self.population_size % 2 == 0 { 2 } else { 1 };
+        let elite_num = if self.population_size.is_multiple_of(2) {
+            2
+        } else {
+            1
+        };
+        let mut top_layouts = TopLayouts::new(options.top_k);
+        top_layouts.insert(&best_layout.layout, best_layout.score);
+        let mut history = Vec::with_capacity(options.iterations);
+        let mut stop_reason = StopReason::Iterations;
+        let mut selectors = vec![OperatorSelector::new(&self.operators); self.island_size];
         let mut count = 0;
-        for i in 0..iterations {
-            islands.par_chunks_mut(1).for_each(|chunk| {
-                let population = &mut chunk[0];
-
-                let sum = population.iter().map(|ind| ind.score).sum::<f32>();
-                let weights: Vec<f32> = population.iter().map(|ind| ind.score / sum).collect();
-                let dist = WeightedIndex::new(weights).unwrap();
-
-                // Keep elite individuals
-                let mut new_population: Vec<Individual> = Vec::with_capacity(self.population_size);
-                new_population.extend(population.iter().take(elite_num).cloned());
-
-                // Crossover
-                let mut children: Vec<Individual> = (0..self.population_size - elite_num)
-                    .into_par_iter()
-                    .map(|_| {
-                        let mut rng = thread_rng();
-                        let mut rng_fast = fastrand::Rng::new();
-                        let parent1_index = dist.sample(&mut rng);
-                        let parent2_index = dist.sample(&mut rng);
-                        if parent1_index == parent2_index {
-                            return population[parent1_index].clone();
+        for i in 0..options.iterations {
+            let refinement = self
+                .memetic
+                .filter(|memetic| i.is_multiple_of(memetic.interval));
+            islands
+                .par_iter_mut()
+                .zip(selectors.par_iter_mut())
+                .for_each(|(population, selector)| {
+                    let scores: Vec<f32> = population.iter().map(|ind| ind.score).collect();
+                    let dist = self.selection.weights(&scores).map(|weights| {
+                        WeightedIndex::new(weights).expect("Failed to create selection weights")
+                    });
+
+                    // Keep elite individuals
+                    let mut new_population: Vec<Individual> =
+                        Vec::with_capacity(self.population_size);
+                    new_population.extend(population.iter().take(elite_num).cloned());
+
+                    // Crossover, keeping the operators and the better parent score of every child
+                    let num_children = self.population_size - elite_num;
+                    let (mut children, choices): (Vec<Individual>, Vec<_>) = (0..num_children)
+                        .into_par_iter()
+                        .map(|_| {
+                            let mut rng = thread_rng();
+                            let mut rng_fast = fastrand::Rng::new();
+                            let parent1_index =
+                                self.selection.sample(&scores, dist.as_ref(), &mut rng);
+                            let parent2_index =
+                                self.selection.sample(&scores, dist.as_ref(), &mut rng);
+                            if parent1_index == parent2_index {
+                                return (population[parent1_index].clone(), None);
+                            }
+                            let parent1 = &population[parent1_index];
+                            let parent2 = &population[parent2_index];
+                            let choice = selector.choose(&mut rng_fast);
+                            let child = parent1.offspring(
+                                parent2,
+                                &choice,
+                                physical_layout,
+                                &mut rng,
+                                &mut rng_fast,
+                            );
+                            (child, Some((choice, parent1.score.min(parent2.score))))
+                        })
+                        .unzip();
+                    new_population.append(&mut children);
+
+                    *population = new_population;
+
+                    // Evaluate population
+                    population.par_iter_mut().for_each(|i| {
+                        i.evaluate(physical_layout, tri_grams);
+                    });
+                    for (child, choice) in population[elite_num..].iter().zip(&choices) {
+                        if let Some((choice, parent_score)) = choice {
+                            selector.reward(choice, *parent_score, child.score);
                         }
-                        let parent1 = &population[parent1_index];
-                        let parent2 = &population[parent2_index];
-                        let mut child = parent1.cyclic_crossover(parent2, usable_chars, &mut rng);
-                        child.mutate(&mut rng_fast);
-                        child
-                    })
-                    .collect();
-                new_population.append(&mut children);
-
-                *population = new_population;
-
-                // Evaluate population
-                population.par_iter_mut().for_each(|i| {
-                    i.evaluate(physical_layout, &tri_grams);
+                    }
+
+                    // Refine offspring or elite individuals
+                    if let (Some(memetic), Some(scorer)) = (refinement, &scorer) {
+                        memetic.refine(population, elite_num, scorer);
+                    }
+
+                    // Sort population by score
+                    population.sort_by(|a, b| {
+                        a.score
+                            .partial_cmp(&b.score)
+                            .expect("Failed to compare scores")
+                    });
                 });
 
-                // Sort population by score
-                population.sort_by(|a, b| {
-                    a.score
-                        .partial_cmp(&b.score)
-                        .expect("Failed to compare scores")
-                });
-            });
-
             // migrate best individuals
-            if i % 10 == 0 {
-                for idx in 0..islands.len() {
-                    let best_individual = islands[idx][0].clone();
-                    let next_idx = (idx + 1) % islands.len();
-                    let next_population = &mut islands[next_idx];
-                    next_population[self.population_size - 1] = best_individual;
-                }
+            if i.is_multiple_of(self.migration.interval) {
+                self.migration.migrate(&mut islands, elite_num, &mut rng);
             }
 
             // update best layout
@@ -132,31 +507,46 @@ impl Genetic {
                 }
             }
 
+            for island in &islands {
+                top_layouts.insert(&island[0].layout, island[0].score);
+            }
+            history.push(best_layout.score);
+
             if i % 100 == 0 {
-                println!("iteration: {} / {}", i, iterations);
+                println!("iteration: {} / {}", i, options.iterations);
                 println!("best score: {}", best_layout.score);
             }
 
             count += 1;
-            if count > early_stop_count {
-                println!("No improvement for {} iterations, stopping...", early_stop_count);
+            if count > options.early_stop_count {
+                println!(
+                    "No improvement for {} iterations, stopping...",
+                    options.early_stop_count
+                );
+                stop_reason = StopReason::NoImprovement;
                 break;
             }
         }
 
-        println!("best score: {}", best_layout.score);
-        physical_layout.print(&best_layout.layout());
+        OptimizeResult {
+            layout: best_layout.layout,
+            score: best_layout.score,
+            iterations: history.len(),
+            stop_reason,
+            history,
+            top_layouts: top_layouts.into_vec(),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
-struct Individual {
-    layout: LogicalLayout,
+pub(crate) struct Individual {
+    pub(crate) layout: LogicalLayout,
     score: f32,
 }
 
 impl Individual {
-    fn new(layout: LogicalLayout) -> Self {
+    pub(crate) fn new(layout: LogicalLayout) -> Self {
         Self { layout, score: 0.0 }
     }
 
@@ -168,97 +558,288 @@ impl Individual {
         self.score = self.layout.evaluate(physical_layout, tri_grams);
     }
 
-    fn cyclic_crossover(
+    /// Crosses the layouts, falling back to the cycle crossover when the child breaks the
+    /// constraints.
+    pub(crate) fn crossover(&self, other: &Self, kind: Crossover, rng: &mut ThreadRng) -> Self {
+        let layout = crossover(kind, &self.layout, &other.layout, rng);
+        if layout.is_valid() {
+            return Self::new(layout);
+        }
+        Self::new(crossover(
+            Crossover::Cycle,
+            &self.layout,
+            &other.layout,
+            rng,
+        ))
+    }
+
+    pub(crate) fn mutate(
+        &mut self,
+        physical_layout: &PhysicalLayout,
+        mutation: Mutation,
+        rng: &mut fastrand::Rng,
+    ) {
+        mutate(mutation, &mut self.layout, physical_layout, rng);
+    }
+
+    /// Crosses the parents and mutates the child with the operators of `choice`.
+    pub(crate) fn offspring(
         &self,
         other: &Self,
-        usable_chars: &[char],
+        choice: &Choice,
+        physical_layout: &PhysicalLayout,
         rng: &mut ThreadRng,
+        rng_fast: &mut fastrand::Rng,
     ) -> Self {
-        let mut new_layout = self.layout.clone();
-
-        let n = self.layout.len();
-        let mut visited = vec![false; n];
-        let mut remaining = n;
+        let mut child = self.crossover(other, choice.crossover, rng);
+        for _ in 0..choice.strength {
+            child.mutate(physical_layout, choice.mutation, rng_fast);
+        }
+        child
+    }
+}
 
-        while remaining > 0 {
-            let idx = loop {
-                let i = rng.gen_range(0..n);
-                if !visited[i] {
-                    break i;
-                }
-            };
-            visited[idx] = true;
-            remaining -= 1;
-            let start_char = self.layout.get(idx);
-            let mut self_char = start_char;
-            loop {
-                let self_char_index = self.layout.get_char_index(self_char);
-                let other_char = other.layout.get(self_char_index);
-                if start_char == other_char {
-                    break;
-                }
-                visited[idx] = true;
-                remaining -= 1;
-                self_char = other_char;
-            }
-            if remaining == 0 {
-                break;
-            }
+impl PartialEq for Individual {
+    fn eq(&self, other: &Self) -> bool {
+        self.score == other.score
+    }
+}
 
-            let idx = loop {
-                let i = rng.gen_range(0..n);
-                if !visited[i] {
-                    break i;
-                }
-            };
-            visited[idx] = true;
-            remaining -= 1;
-            let mut other_char = start_char;
-            loop {
-                let other_char_index = other.layout.get_char_index(other_char);
-                let self_char = self.layout.get(other_char_index);
-                new_layout.set(other_char_index, other_char);
-                if start_char == self_char {
-                    break;
-                }
-                visited[idx] = true;
-                remaining -= 1;
-                other_char = self_char;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::{sample_physical_layout, Constraints};
+
+    #[test]
+    fn test_selection() {
+        let scores = [3.0, 1.0, 5.0, 2.0, 4.0];
+        let mut rng = thread_rng();
+        let strategies = [
+            Selection::Tournament { size: 3 },
+            Selection::Rank { pressure: 1.8 },
+            Selection::InverseCost,
+            Selection::Truncation { fraction: 0.4 },
+        ];
+        for selection in strategies {
+            let dist = selection
+                .weights(&scores)
+                .map(|weights| WeightedIndex::new(weights).unwrap());
+            let mut picks = [0; 5];
+            for _ in 0..2000 {
+                picks[selection.sample(&scores, dist.as_ref(), &mut rng)] += 1;
             }
+            // the best layout is picked more often than the worst, and on average below the mean
+            assert!(picks[1] > picks[2], "{:?}: {:?}", selection, picks);
+            let mean = picks
+                .iter()
+                .zip(scores)
+                .map(|(count, score)| *count as f32 * score)
+                .sum::<f32>()
+                / 2000.0;
+            assert!(mean < 3.0, "{:?}: {}", selection, mean);
         }
 
-        Self::new(new_layout)
+        let weights = Selection::Truncation { fraction: 0.4 }
+            .weights(&scores)
+            .unwrap();
+        assert_eq!(weights, vec![0.0, 1.0, 0.0, 1.0, 0.0]);
+        let weights = Selection::Rank { pressure: 2.0 }.weights(&scores).unwrap();
+        assert_eq!(weights, vec![1.0, 2.0, 0.0, 1.5, 0.5]);
     }
 
-    fn random_mutation(&mut self, rng: &mut fastrand::Rng) {
-        let a = rng.usize(0..self.layout.len());
-        let b = rng.usize(0..self.layout.len());
-        self.layout.swap(a, b);
-    }
-
-    fn mutate(&mut self, rng: &mut fastrand::Rng) {
-        let mutation_type = rng.u8(0..4);
-        match mutation_type {
-            0 => (),
-            _ => self.random_mutation(rng),
+    #[test]
+    fn test_migration() {
+        let mut rng = fastrand::Rng::with_seed(9);
+        assert_eq!(Topology::Ring.targets(3, 4, &mut rng), vec![0]);
+        assert_eq!(
+            Topology::FullyConnected.targets(1, 4, &mut rng),
+            vec![0, 2, 3]
+        );
+        assert_eq!(
+            Topology::Torus { width: 3 }.targets(0, 6, &mut rng),
+            vec![1, 2, 3]
+        );
+        assert!((0..20).all(|_| Topology::Random.targets(2, 4, &mut rng)[0] != 2));
+        assert!(Topology::Ring.targets(0, 1, &mut rng).is_empty());
+        let torus = Topology::Torus { width: 2 };
+        for island in 0..6 {
+            for target in torus.targets(island, 6, &mut rng) {
+                assert!(torus.targets(target, 6, &mut rng).contains(&island));
+            }
         }
-    }
 
-    fn layout(&self) -> Vec<char> {
-        self.layout.clone().output()
+        let physical_layout = sample_physical_layout();
+        let layout = LogicalLayout::from_usable_chars(&physical_layout, vec!['a', 'b']);
+        // every individual has its own layout, with 'a' on the slot of its score
+        let island = |scores: &[f32]| -> Vec<Individual> {
+            scores
+                .iter()
+                .map(|score| {
+                    let mut layout = layout.clone();
+                    layout.swap(0, *score as usize + 1);
+                    Individual {
+                        layout,
+                        score: *score,
+                    }
+                })
+                .collect()
+        };
+        let slots = |population: &[Individual]| -> Vec<Vec<Option<char>>> {
+            population
+                .iter()
+                .map(|i| i.layout.slots().to_vec())
+                .collect()
+        };
+        let scores = |islands: &[Vec<Individual>]| -> Vec<Vec<f32>> {
+            islands
+                .iter()
+                .map(|population| population.iter().map(|i| i.score).collect())
+                .collect()
+        };
+
+        let migration = Migration {
+            topology: Topology::Ring,
+            interval: 1,
+            migrants: 2,
+            replacement: Replacement::Worst,
+        };
+        let mut islands = vec![island(&[1.0, 2.0, 3.0, 4.0]), island(&[5.0, 6.0, 7.0, 8.0])];
+        let (first, second) = (slots(&islands[0]), slots(&islands[1]));
+        migration.migrate(&mut islands, 1, &mut rng);
+        assert_eq!(
+            scores(&islands),
+            vec![vec![1.0, 2.0, 5.0, 6.0], vec![1.0, 2.0, 5.0, 6.0]]
+        );
+        // the best two of each island replace the worst two of the other
+        assert_eq!(slots(&islands[0])[..2], first[..2]);
+        assert_eq!(slots(&islands[0])[2..], second[..2]);
+        assert_eq!(slots(&islands[1])[..2], first[..2]);
+        assert_eq!(slots(&islands[1])[2..], second[..2]);
+
+        let migration = Migration {
+            replacement: Replacement::IfBetter,
+            ..migration
+        };
+        let mut islands = vec![island(&[1.0, 2.0, 3.0, 4.0]), island(&[5.0, 6.0, 7.0, 8.0])];
+        migration.migrate(&mut islands, 1, &mut rng);
+        assert_eq!(
+            scores(&islands),
+            vec![vec![1.0, 2.0, 3.0, 4.0], vec![1.0, 2.0, 5.0, 6.0]]
+        );
     }
-}
 
-impl PartialEq for Individual {
-    fn eq(&self, other: &Self) -> bool {
-        self.score == other.score
+    #[test]
+    fn test_constraints() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefghij".chars().collect();
+        let mut initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let mut constraints = Constraints::new();
+        constraints
+            .pin('j', 9)
+            .allow('a', [10, 11, 12, 13])
+            .forbid('b', 10..30);
+        initial_layout.set_constraints(&constraints);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+            (LogicalNGram::new(['g', 'h', 'i']), 0.2),
+        ]
+        .into_iter()
+        .collect();
+
+        let genetic = Genetic::new(6, 2).with_operators(Operators {
+            crossovers: vec![
+                (Crossover::Cycle, 1.0),
+                (Crossover::Pmx, 1.0),
+                (Crossover::Order, 1.0),
+                (Crossover::PositionBased, 1.0),
+            ],
+            mutations: vec![(Mutation::Swap, 1.0), (Mutation::RowSwap, 1.0)],
+            ..Default::default()
+        });
+        let options = RunOptions {
+            iterations: 10,
+            shuffle: true,
+            early_stop_count: 10,
+            top_k: 3,
+        };
+        let result = genetic.run(&physical_layout, &initial_layout, &tri_grams, &options);
+        assert!(result.layout.is_valid());
+        assert!(result
+            .top_layouts
+            .iter()
+            .all(|(layout, _)| layout.is_valid()));
+        assert_eq!(result.layout.get(9), Some('j'));
     }
-}
 
-fn rand_pop(set: &mut HashSet<char>, rng: &mut ThreadRng) -> char {
-    let char = *set.iter().choose(rng).expect("No usable chars found");
-    set.remove(&char);
-    char
+    #[test]
+    fn test_memetic() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefgh".chars().collect();
+        let initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+            (LogicalNGram::new(['g', 'h', 'a']), 0.2),
+        ]
+        .into_iter()
+        .collect();
+        let initial_score = initial_layout.evaluate(&physical_layout, &tri_grams);
+
+        // 'a' to 'h' start on the top row, so refinement finds improving swaps
+        let mut layout = initial_layout.clone();
+        let mut rng = fastrand::Rng::with_seed(1);
+        let scorer = SwapScorer::new(&initial_layout, &physical_layout, &tri_grams);
+        let score = refine(&mut layout, initial_score, &scorer, 3, 50, &mut rng);
+        assert!(score < initial_score);
+        assert!((layout.evaluate(&physical_layout, &tri_grams) - score).abs() < 1e-4);
+
+        let individual = Individual {
+            layout: initial_layout.clone(),
+            score: initial_score,
+        };
+        let mut population = vec![individual; 4];
+        let memetic = Memetic {
+            target: RefineTarget::Elite,
+            interval: 1,
+            depth: 2,
+            samples: 50,
+        };
+        memetic.refine(&mut population, 1, &scorer);
+        let elite_score = population[0].score;
+        assert!(elite_score < initial_score);
+        assert!(population[1..].iter().all(|i| i.score == initial_score));
+        let memetic = Memetic {
+            target: RefineTarget::Offspring,
+            ..memetic
+        };
+        memetic.refine(&mut population, 1, &scorer);
+        assert_eq!(population[0].score, elite_score);
+        assert!(population[1..].iter().all(|i| i.score < initial_score));
+
+        for target in [RefineTarget::Elite, RefineTarget::Offspring] {
+            let genetic = Genetic::new(4, 1).with_memetic(Memetic {
+                target,
+                interval: 2,
+                depth: 2,
+                samples: 20,
+            });
+            let options = RunOptions {
+                iterations: 6,
+                shuffle: false,
+                early_stop_count: 6,
+                top_k: 1,
+            };
+            let result = genetic.run(&physical_layout, &initial_layout, &tri_grams, &options);
+            assert!(result.score <= initial_score);
+            let evaluated = result.layout.evaluate(&physical_layout, &tri_grams);
+            assert!((evaluated - result.score).abs() < 1e-4);
+        }
+    }
 }
 
 // #[cfg(test)]
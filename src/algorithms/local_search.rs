@@ -0,0 +1,405 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+use super::optimizer::{OptimizeResult, Optimizer, RunOptions, StopReason, TopLayouts};
+use crate::keyboard_layout::{LogicalLayout, PhysicalLayout};
+use crate::n_gram::LogicalNGram;
+
+/// Scores swaps of two slots by re-evaluating only the tri-grams that touch the swapped chars.
+pub(crate) struct SwapScorer<'a> {
+    physical_layout: &'a PhysicalLayout,
+    tri_grams: &'a HashMap<LogicalNGram<3>, f32>,
+    entries: Vec<(&'a LogicalNGram<3>, f32)>,
+    /// The entries listed under every char whose slot decides their cost.
+    touching: HashMap<char, Vec<usize>>,
+}
+
+impl<'a> SwapScorer<'a> {
+    /// Indexes `tri_grams` for the layouts sharing the chars and settings of `layout`.
+    pub(crate) fn new(
+        layout: &LogicalLayout,
+        physical_layout: &'a PhysicalLayout,
+        tri_grams: &'a HashMap<LogicalNGram<3>, f32>,
+    ) -> Self {
+        let entries: Vec<(&LogicalNGram<3>, f32)> = tri_grams
+            .iter()
+            .map(|(n_gram, score)| (n_gram, *score))
+            .collect();
+        let mut touching: HashMap<char, Vec<usize>> = HashMap::new();
+        for (i, (n_gram, _)) in entries.iter().enumerate() {
+            for c in layout.tri_gram_chars(n_gram) {
+                touching.entry(c).or_default().push(i);
+            }
+        }
+        Self {
+            physical_layout,
+            tri_grams,
+            entries,
+            touching,
+        }
+    }
+
+    /// The full score of `layout`.
+    pub(crate) fn score(&self, layout: &LogicalLayout) -> f32 {
+        layout.evaluate(self.physical_layout, self.tri_grams)
+    }
+
+    /// The change of the score of `layout` when slots `a` and `b` are swapped. `layout` is left
+    /// as it was.
+    pub(crate) fn delta(&self, layout: &mut LogicalLayout, a: usize, b: usize) -> f32 {
+        let mut affected: Vec<usize> = Vec::new();
+        for c in [layout.get(a), layout.get(b)].into_iter().flatten() {
+            affected.extend(self.touching.get(&c).into_iter().flatten());
+        }
+        affected.sort_unstable();
+        affected.dedup();
+        let mut keys = Vec::new();
+        let mut cost = |layout: &LogicalLayout| -> f32 {
+            let tri_gram_cost: f32 = affected
+                .iter()
+                .map(|i| {
+                    let (n_gram, score) = self.entries[*i];
+                    score * layout.tri_gram_cost(self.physical_layout, n_gram, &mut keys)
+                })
+                .sum();
+            tri_gram_cost + layout.penalty(self.physical_layout)
+        };
+        let before = cost(layout);
+        layout.swap(a, b);
+        let after = cost(layout);
+        layout.swap(a, b);
+        after - before
+    }
+}
+
+/// Every swap of two slots that changes the layout and keeps its constraints, with the change of
+/// the score it makes.
+fn swap_neighbors(layout: &LogicalLayout, scorer: &SwapScorer) -> Vec<(usize, usize, f32)> {
+    let n = layout.len();
+    let pairs: Vec<(usize, usize)> = (0..n)
+        .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+        .filter(|(a, b)| layout.get(*a) != layout.get(*b) && layout.can_swap(*a, *b))
+        .collect();
+    pairs
+        .into_par_iter()
+        .map_init(
+            || layout.clone(),
+            |neighbor, (a, b)| (a, b, scorer.delta(neighbor, a, b)),
+        )
+        .collect()
+}
+
+/// Bounded first-improvement search: applies up to `depth` improving swaps, each found among at
+/// most `samples` random swaps, and returns the new score.
+pub(crate) fn refine(
+    layout: &mut LogicalLayout,
+    score: f32,
+    scorer: &SwapScorer,
+    depth: usize,
+    samples: usize,
+    rng: &mut fastrand::Rng,
+) -> f32 {
+    let n = layout.len();
+    let mut improved = false;
+    for _ in 0..depth {
+        let mut found = false;
+        for _ in 0..samples {
+            let a = rng.usize(0..n);
+            let b = rng.usize(0..n);
+            if layout.get(a) == layout.get(b) || !layout.can_swap(a, b) {
+                continue;
+            }
+            if scorer.delta(layout, a, b) < 0.0 {
+                layout.swap(a, b);
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            break;
+        }
+        improved = true;
+    }
+    if improved {
+        scorer.score(layout)
+    } else {
+        score
+    }
+}
+
+fn initial_layout_of(initial_layout: &LogicalLayout, shuffle: bool) -> LogicalLayout {
+    let mut layout = initial_layout.clone();
+    if shuffle {
+        layout.shuffle(&mut fastrand::Rng::new());
+    }
+    layout
+}
+
+/// Steepest descent: applies the best swap of two slots until no swap improves the layout.
+pub struct HillClimbing;
+
+impl Optimizer for HillClimbing {
+    fn run(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        options: &RunOptions,
+    ) -> OptimizeResult {
+        let mut layout = initial_layout_of(initial_layout, options.shuffle);
+        let scorer = SwapScorer::new(&layout, physical_layout, tri_grams);
+        let mut score = scorer.score(&layout);
+        let mut top_layouts = TopLayouts::new(options.top_k);
+        top_layouts.insert(&layout, score);
+        let mut history = Vec::with_capacity(options.iterations);
+        let mut stop_reason = StopReason::Iterations;
+
+        for i in 0..options.iterations {
+            let best_move = swap_neighbors(&layout, &scorer)
+                .into_iter()
+                .min_by(|x, y| x.2.partial_cmp(&y.2).expect("Failed to compare scores"));
+            match best_move {
+                Some((a, b, delta)) if delta < 0.0 => {
+                    layout.swap(a, b);
+                    score = scorer.score(&layout);
+                    top_layouts.insert(&layout, score);
+                }
+                _ => {
+                    stop_reason = StopReason::LocalOptimum;
+                    break;
+                }
+            }
+            history.push(score);
+
+            if i % 100 == 0 {
+                println!("iteration: {} / {}", i, options.iterations);
+                println!("best score: {}", score);
+            }
+        }
+
+        OptimizeResult {
+            layout,
+            score,
+            iterations: history.len(),
+            stop_reason,
+            history,
+            top_layouts: top_layouts.into_vec(),
+        }
+    }
+}
+
+/// The iteration until which a char may not return to a slot.
+type TabuList = HashMap<(Option<char>, usize), usize>;
+
+/// The best of `neighbors` that does not put both chars back on slots they left, or that does
+/// but beats `best_score`.
+fn best_allowed_swap(
+    layout: &LogicalLayout,
+    neighbors: Vec<(usize, usize, f32)>,
+    tabu_until: &TabuList,
+    iteration: usize,
+    score: f32,
+    best_score: f32,
+) -> Option<(usize, usize, f32)> {
+    let is_tabu = |c, slot| {
+        tabu_until
+            .get(&(c, slot))
+            .is_some_and(|until| *until > iteration)
+    };
+    neighbors
+        .into_iter()
+        .filter(|(a, b, delta)| {
+            let tabu = is_tabu(layout.get(*a), *b) && is_tabu(layout.get(*b), *a);
+            !tabu || score + delta < best_score
+        })
+        .min_by(|x, y| x.2.partial_cmp(&y.2).expect("Failed to compare scores"))
+}
+
+/// Robust tabu search over swaps of two slots, as used for the quadratic assignment problem.
+///
+/// A swap is tabu when it puts both chars back on slots they left within their tenure, which is
+/// drawn from `min_tenure..=max_tenure` for every move. A tabu swap is still taken when it beats
+/// the best layout found so far.
+pub struct TabuSearch {
+    min_tenure: usize,
+    max_tenure: usize,
+    seed: u64,
+}
+
+impl TabuSearch {
+    pub fn new(min_tenure: usize, max_tenure: usize, seed: u64) -> Self {
+        if min_tenure > max_tenure {
+            panic!("min_tenure must not be greater than max_tenure");
+        }
+        Self {
+            min_tenure,
+            max_tenure,
+            seed,
+        }
+    }
+}
+
+impl Optimizer for TabuSearch {
+    fn run(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        options: &RunOptions,
+    ) -> OptimizeResult {
+        let mut rng = fastrand::Rng::with_seed(self.seed);
+        let mut layout = initial_layout_of(initial_layout, options.shuffle);
+        let scorer = SwapScorer::new(&layout, physical_layout, tri_grams);
+        let mut score = scorer.score(&layout);
+        let mut best = layout.clone();
+        let mut best_score = score;
+        let mut top_layouts = TopLayouts::new(options.top_k);
+        top_layouts.insert(&layout, score);
+
+        let mut tabu_until = TabuList::new();
+
+        let mut history = Vec::with_capacity(options.iterations);
+        let mut stop_reason = StopReason::Iterations;
+        let mut count = 0;
+        for i in 0..options.iterations {
+            let neighbors = swap_neighbors(&layout, &scorer);
+            let best_move =
+                best_allowed_swap(&layout, neighbors, &tabu_until, i, score, best_score);
+            let Some((a, b, _)) = best_move else {
+                stop_reason = StopReason::LocalOptimum;
+                break;
+            };
+
+            let tenure = rng.usize(self.min_tenure..=self.max_tenure);
+            tabu_until.insert((layout.get(a), a), i + tenure);
+            tabu_until.insert((layout.get(b), b), i + tenure);
+            layout.swap(a, b);
+            score = scorer.score(&layout);
+            top_layouts.insert(&layout, score);
+            if score < best_score {
+                best = layout.clone();
+                best_score = score;
+                count = 0;
+            }
+            history.push(best_score);
+
+            if i % 100 == 0 {
+                println!("iteration: {} / {}", i, options.iterations);
+                println!("best score: {}", best_score);
+            }
+
+            count += 1;
+            if count > options.early_stop_count {
+                println!(
+                    "No improvement for {} iterations, stopping...",
+                    options.early_stop_count
+                );
+                stop_reason = StopReason::NoImprovement;
+                break;
+            }
+        }
+
+        OptimizeResult {
+            layout: best,
+            score: best_score,
+            iterations: history.len(),
+            stop_reason,
+            history,
+            top_layouts: top_layouts.into_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::sample_physical_layout;
+
+    #[test]
+    fn test_local_search() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefgh".chars().collect();
+        let initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+            (LogicalNGram::new(['g', 'h', 'a']), 0.2),
+        ]
+        .into_iter()
+        .collect();
+        let initial_score = initial_layout.evaluate(&physical_layout, &tri_grams);
+        let options = RunOptions {
+            iterations: 50,
+            shuffle: false,
+            early_stop_count: 5,
+            top_k: 3,
+        };
+
+        let result = HillClimbing.run(&physical_layout, &initial_layout, &tri_grams, &options);
+        assert_eq!(result.stop_reason, StopReason::LocalOptimum);
+        assert!(result.score < initial_score);
+        assert!(result.history.windows(2).all(|w| w[1] < w[0]));
+        let scorer = SwapScorer::new(&result.layout, &physical_layout, &tri_grams);
+        let neighbors = swap_neighbors(&result.layout, &scorer);
+        assert!(neighbors.iter().all(|(_, _, delta)| *delta >= 0.0));
+        let (a, b, delta) = neighbors[0];
+        let mut swapped = result.layout.clone();
+        swapped.swap(a, b);
+        let swapped_score = swapped.evaluate(&physical_layout, &tri_grams);
+        assert!((result.score + delta - swapped_score).abs() < 1e-4);
+
+        let tabu_search = TabuSearch::new(3, 6, 42);
+        let tabu = tabu_search.run(&physical_layout, &initial_layout, &tri_grams, &options);
+        assert!(tabu.score <= result.score + 1e-4);
+        let again = tabu_search.run(&physical_layout, &initial_layout, &tri_grams, &options);
+        assert_eq!(again.layout.slots(), tabu.layout.slots());
+    }
+
+    #[test]
+    fn test_aspiration() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefgh".chars().collect();
+        let layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+        ]
+        .into_iter()
+        .collect();
+        let scorer = SwapScorer::new(&layout, &physical_layout, &tri_grams);
+        let score = scorer.score(&layout);
+        let neighbors = swap_neighbors(&layout, &scorer);
+        let (a, b, delta) = neighbors
+            .iter()
+            .copied()
+            .min_by(|x, y| x.2.partial_cmp(&y.2).unwrap())
+            .unwrap();
+        assert!(delta < 0.0);
+
+        // both chars of the best swap would return to slots they left
+        let mut tabu_until = TabuList::new();
+        tabu_until.insert((layout.get(a), b), 5);
+        tabu_until.insert((layout.get(b), a), 5);
+
+        // taken while it beats the best score, skipped otherwise
+        let chosen = best_allowed_swap(&layout, neighbors.clone(), &tabu_until, 0, score, score);
+        assert_eq!(chosen.map(|(a, b, _)| (a, b)), Some((a, b)));
+        let best_score = score + delta;
+        let chosen = best_allowed_swap(
+            &layout,
+            neighbors.clone(),
+            &tabu_until,
+            0,
+            score,
+            best_score,
+        );
+        assert_ne!(chosen.map(|(a, b, _)| (a, b)), Some((a, b)));
+        // and taken again once its tenure is over
+        let chosen = best_allowed_swap(&layout, neighbors, &tabu_until, 5, score, best_score);
+        assert_eq!(chosen.map(|(a, b, _)| (a, b)), Some((a, b)));
+    }
+}
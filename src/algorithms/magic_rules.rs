@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+
+use super::genetic::Genetic;
+use super::optimizer::{Optimizer, RunOptions};
+use crate::keyboard_layout::{LogicalLayout, PhysicalLayout, MAGIC_KEY};
+use crate::n_gram::LogicalNGram;
+
+/// Jointly optimizes key placement and the rules of the magic key.
+///
+/// Each round optimizes the layout with `Genetic` for the current rules, then greedily chooses
+/// the output of the magic key after every char while the layout is fixed, until no rule changes.
+pub struct MagicRuleSearch {
+    genetic: Genetic,
+    rounds: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MagicRuleResult {
+    pub layout: LogicalLayout,
+    pub rules: HashMap<char, char>,
+    pub score: f32,
+}
+
+impl MagicRuleSearch {
+    pub fn new(genetic: Genetic, rounds: usize) -> Self {
+        Self { genetic, rounds }
+    }
+
+    /// `initial_layout` must place `MAGIC_KEY`; its magic rules are the starting rules.
+    pub fn optimize(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        iterations: usize,
+        early_stop_count: usize,
+    ) -> MagicRuleResult {
+        assert!(
+            initial_layout.chars().any(|c| c == MAGIC_KEY),
+            "the magic key must be placed on the layout"
+        );
+
+        let pairs = Self::pairs(tri_grams);
+        let mut layout = initial_layout.clone();
+        let mut best: Option<MagicRuleResult> = None;
+
+        for round in 0..self.rounds {
+            let round_rules = layout.magic_rules().clone();
+            let options = RunOptions {
+                iterations,
+                shuffle: round == 0,
+                early_stop_count,
+                top_k: 1,
+            };
+            let result = self
+                .genetic
+                .run(physical_layout, &layout, tri_grams, &options);
+            let mut score = result.score;
+            layout = result.layout;
+
+            for (prev, candidates) in &pairs {
+                let affected: HashMap<LogicalNGram<3>, f32> = tri_grams
+                    .iter()
+                    .filter(|(n_gram, _)| n_gram.get(0) == *prev || n_gram.get(1) == *prev)
+                    .map(|(n_gram, score)| {
+                        let chars = [n_gram.get(0), n_gram.get(1), n_gram.get(2)];
+                        (LogicalNGram::new(chars), *score)
+                    })
+                    .collect();
+                let mut rules = layout.magic_rules().clone();
+                let mut best_output = rules.get(prev).copied();
+                let mut best_cost = layout.evaluate(physical_layout, &affected);
+                let base_cost = best_cost;
+                for output in std::iter::once(None).chain(candidates.iter().copied().map(Some)) {
+                    if output == rules.get(prev).copied() {
+                        continue;
+                    }
+                    match output {
+                        Some(output) => rules.insert(*prev, output),
+                        None => rules.remove(prev),
+                    };
+                    let mut candidate = layout.clone();
+                    candidate.set_magic_rules(rules.clone());
+                    let cost = candidate.evaluate(physical_layout, &affected);
+                    if cost < best_cost {
+                        best_cost = cost;
+                        best_output = output;
+                    }
+                }
+                match best_output {
+                    Some(output) => rules.insert(*prev, output),
+                    None => rules.remove(prev),
+                };
+                layout.set_magic_rules(rules);
+                score += best_cost - base_cost;
+            }
+
+            println!("round: {} / {}", round + 1, self.rounds);
+            println!("score: {}", score);
+
+            if best.as_ref().is_none_or(|best| score < best.score) {
+                best = Some(MagicRuleResult {
+                    layout: layout.clone(),
+                    rules: layout.magic_rules().clone(),
+                    score,
+                });
+            }
+            if *layout.magic_rules() == round_rules {
+                break;
+            }
+        }
+
+        best.expect("rounds must be greater than 0")
+    }
+
+    /// The chars following each char in `tri_grams`, which are the candidate rule outputs.
+    fn pairs(tri_grams: &HashMap<LogicalNGram<3>, f32>) -> Vec<(char, Vec<char>)> {
+        let mut pairs: HashMap<char, HashSet<char>> = HashMap::new();
+        for n_gram in tri_grams.keys() {
+            for i in 1..3 {
+                let (prev, c) = (n_gram.get(i - 1), n_gram.get(i));
+                if prev != c {
+                    pairs.entry(prev).or_default().insert(c);
+                }
+            }
+        }
+        let mut pairs: Vec<(char, Vec<char>)> = pairs
+            .into_iter()
+            .map(|(prev, outputs)| {
+                let mut outputs: Vec<char> = outputs.into_iter().collect();
+                outputs.sort_unstable();
+                (prev, outputs)
+            })
+            .collect();
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::sample_physical_layout;
+
+    #[test]
+    fn test_magic_rules() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let mut chars: Vec<char> = "abcdefghijklmnopqrst".chars().collect();
+        chars.push(MAGIC_KEY);
+        let initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'k', 'o']), 0.4),
+            (LogicalNGram::new(['b', 'e', 'd']), 0.3),
+            (LogicalNGram::new(['c', 'a', 'f']), 0.3),
+        ]
+        .into_iter()
+        .collect();
+
+        let search = MagicRuleSearch::new(Genetic::new(4, 1), 2);
+        let result = search.optimize(&physical_layout, &initial_layout, &tri_grams, 3, 3);
+        assert_eq!(result.rules, *result.layout.magic_rules());
+        let mut evaluated = result.layout.clone();
+        evaluated.set_magic_rules(result.rules.clone());
+        assert!((evaluated.evaluate(&physical_layout, &tri_grams) - result.score).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,503 @@
+use rand::thread_rng;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::genetic::Individual;
+use super::operators::{OperatorSelector, Operators};
+use super::optimizer::RunOptions;
+use crate::keyboard_layout::{Hand, LogicalLayout, PhysicalLayout};
+use crate::n_gram::LogicalNGram;
+
+/// A criterion of the multi-objective search; every objective is minimized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    /// The score of `LogicalLayout::effort`: the typing cost and the soft constraint penalties,
+    /// without the learning cost, which is the separate `Learning` objective.
+    Effort,
+    /// The difference between the shares of keystrokes typed by the two hands, as a fraction
+    /// of all keystrokes on the layout. Each tri-gram counts only its last char, the keystroke
+    /// it adds to the text, so every keystroke is counted once.
+    HandBalance,
+    /// The distance of the learning cost set on the layout, which must have one.
+    Learning,
+    /// The share of consecutive keystrokes typed by the same finger on different keys.
+    SameFinger,
+}
+
+impl Objective {
+    pub fn value(
+        &self,
+        physical_layout: &PhysicalLayout,
+        layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+    ) -> f32 {
+        match self {
+            Objective::Effort => layout.effort(physical_layout, tri_grams),
+            Objective::HandBalance => {
+                let (mut left, mut right) = (0.0, 0.0);
+                for (n_gram, frequency) in tri_grams {
+                    match layout
+                        .key(n_gram.get(2))
+                        .map(|key| physical_layout.hand(key))
+                    {
+                        Some(Hand::Left) => left += frequency,
+                        Some(Hand::Right) => right += frequency,
+                        _ => (),
+                    }
+                }
+                if left + right > 0.0 {
+                    (left - right).abs() / (left + right)
+                } else {
+                    0.0
+                }
+            }
+            Objective::Learning => layout
+                .learning_distance(physical_layout)
+                .expect("Objective::Learning needs a learning cost on the layout"),
+            Objective::SameFinger => {
+                let (mut same, mut total) = (0.0, 0.0);
+                for (n_gram, frequency) in tri_grams {
+                    for i in 0..2 {
+                        total += frequency;
+                        let keys = (layout.key(n_gram.get(i)), layout.key(n_gram.get(i + 1)));
+                        let (Some(a), Some(b)) = keys else {
+                            continue;
+                        };
+                        let finger_a = physical_layout.finger(a).unwrap_or_default();
+                        let finger_b = physical_layout.finger(b).unwrap_or_default();
+                        if a != b
+                            && physical_layout.hand(a).same(physical_layout.hand(b))
+                            && finger_a.intersects(finger_b)
+                        {
+                            same += frequency;
+                        }
+                    }
+                }
+                if total > 0.0 {
+                    same / total
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// A layout of the Pareto front with its value for every objective.
+#[derive(Debug, Clone)]
+pub struct ParetoLayout {
+    pub layout: LogicalLayout,
+    pub values: Vec<f32>,
+}
+
+/// The non-dominated layouts of a multi-objective search, sorted by the first objective.
+#[derive(Debug, Clone)]
+pub struct ParetoFront {
+    pub objectives: Vec<Objective>,
+    pub layouts: Vec<ParetoLayout>,
+    pub iterations: usize,
+}
+
+impl ParetoFront {
+    pub fn print(&self, physical_layout: &PhysicalLayout) {
+        for pareto_layout in &self.layouts {
+            for (objective, value) in self.objectives.iter().zip(&pareto_layout.values) {
+                println!("{:?}: {}", objective, value);
+            }
+            physical_layout.print(&pareto_layout.layout.layer(0));
+        }
+    }
+
+    /// Writes the front as CSV with a column per objective and the base layer of every layout,
+    /// for plotting.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header: Vec<String> = self
+            .objectives
+            .iter()
+            .map(|objective| format!("{:?}", objective))
+            .collect();
+        writeln!(writer, "{},layout", header.join(","))?;
+        for pareto_layout in &self.layouts {
+            let values: Vec<String> = pareto_layout
+                .values
+                .iter()
+                .map(|value| value.to_string())
+                .collect();
+            let layer: String = pareto_layout.layout.layer(0).into_iter().collect();
+            writeln!(
+                writer,
+                "{},\"{}\"",
+                values.join(","),
+                layer.replace('"', "\"\"")
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+/// Whether `a` is no worse than `b` in every objective and better in one.
+fn dominates(a: &[f32], b: &[f32]) -> bool {
+    a.iter().zip(b).all(|(x, y)| x <= y) && a.iter().zip(b).any(|(x, y)| x < y)
+}
+
+/// Splits the indices of `values` into fronts, the first one being non-dominated.
+fn non_dominated_sort(values: &[Vec<f32>]) -> Vec<Vec<usize>> {
+    let n = values.len();
+    let mut dominated: Vec<Vec<usize>> = vec![Vec::new(); n];
+    let mut domination_count = vec![0; n];
+    for a in 0..n {
+        for b in a + 1..n {
+            if dominates(&values[a], &values[b]) {
+                dominated[a].push(b);
+                domination_count[b] += 1;
+            } else if dominates(&values[b], &values[a]) {
+                dominated[b].push(a);
+                domination_count[a] += 1;
+            }
+        }
+    }
+
+    let mut fronts = Vec::new();
+    let mut front: Vec<usize> = (0..n).filter(|i| domination_count[*i] == 0).collect();
+    while !front.is_empty() {
+        let mut next = Vec::new();
+        for a in &front {
+            for b in &dominated[*a] {
+                domination_count[*b] -= 1;
+                if domination_count[*b] == 0 {
+                    next.push(*b);
+                }
+            }
+        }
+        fronts.push(front);
+        front = next;
+    }
+    fronts
+}
+
+/// The crowding distance of every member of `front`, infinite at the extremes of an objective.
+fn crowding_distance(values: &[Vec<f32>], front: &[usize]) -> Vec<f32> {
+    let mut distances = vec![0.0; front.len()];
+    let num_objectives = values.get(front[0]).map_or(0, |v| v.len());
+    let columns = (0..num_objectives).map(|m| front.iter().map(move |i| values[*i][m]));
+    for column in columns {
+        let column: Vec<f32> = column.collect();
+        let mut order: Vec<usize> = (0..front.len()).collect();
+        order.sort_by(|a, b| {
+            column[*a]
+                .partial_cmp(&column[*b])
+                .expect("Failed to compare objective values")
+        });
+        let first = order[0];
+        let last = order[order.len() - 1];
+        distances[first] = f32::INFINITY;
+        distances[last] = f32::INFINITY;
+        let range = column[last] - column[first];
+        if range <= 0.0 {
+            continue;
+        }
+        for window in order.windows(3) {
+            distances[window[1]] += (column[window[2]] - column[window[0]]) / range;
+        }
+    }
+    distances
+}
+
+/// NSGA-II search for the Pareto front of several objectives.
+///
+/// Offspring come from the crossover and mutation of the genetic search, and the next population
+/// is filled front by front, preferring the least crowded layouts of the last front.
+pub struct Nsga2 {
+    population_size: usize,
+    objectives: Vec<Objective>,
+    operators: Operators,
+}
+
+impl Nsga2 {
+    pub fn new(population_size: usize, objectives: Vec<Objective>) -> Self {
+        if population_size < 2 {
+            panic!("population_size must be greater than 1");
+        }
+        if objectives.is_empty() {
+            panic!("objectives must not be empty");
+        }
+        Self {
+            population_size,
+            objectives,
+            operators: Operators::default(),
+        }
+    }
+
+    /// Sets the weighted operators. Adaptation needs a score to credit children with, which a
+    /// Pareto front does not have, so it is rejected.
+    pub fn with_operators(mut self, operators: Operators) -> Self {
+        operators.validate();
+        if operators.adaptation.is_some() {
+            panic!("Nsga2 does not support operator adaptation");
+        }
+        self.operators = operators;
+        self
+    }
+
+    fn values(
+        &self,
+        physical_layout: &PhysicalLayout,
+        layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+    ) -> Vec<f32> {
+        self.objectives
+            .iter()
+            .map(|objective| objective.value(physical_layout, layout, tri_grams))
+            .collect()
+    }
+
+    /// The rank and crowding distance of every member, ranks counting fronts from 0.
+    fn rank(values: &[Vec<f32>]) -> (Vec<Vec<usize>>, Vec<usize>, Vec<f32>) {
+        let fronts = non_dominated_sort(values);
+        let mut ranks = vec![0; values.len()];
+        let mut crowding = vec![0.0; values.len()];
+        for (rank, front) in fronts.iter().enumerate() {
+            for (i, distance) in front.iter().zip(crowding_distance(values, front)) {
+                ranks[*i] = rank;
+                crowding[*i] = distance;
+            }
+        }
+        (fronts, ranks, crowding)
+    }
+
+    pub fn run(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        options: &RunOptions,
+    ) -> ParetoFront {
+        if self.objectives.contains(&Objective::Learning)
+            && initial_layout.learning_distance(physical_layout).is_none()
+        {
+            panic!("Objective::Learning needs a learning cost set on the initial layout");
+        }
+        let mut rng = fastrand::Rng::new();
+        let mut thread_rng = thread_rng();
+        let mut population: Vec<Individual> = (0..self.population_size)
+            .map(|i| {
+                let mut layout = initial_layout.clone();
+                if options.shuffle || i > 0 {
+                    layout.shuffle(&mut rng);
+                }
+                Individual::new(layout)
+            })
+            .collect();
+        let mut values: Vec<Vec<f32>> = population
+            .par_iter()
+            .map(|individual| self.values(physical_layout, &individual.layout, tri_grams))
+            .collect();
+
+        let selector = OperatorSelector::new(&self.operators);
+        let mut iterations = 0;
+        let mut count = 0;
+        for i in 0..options.iterations {
+            iterations += 1;
+            let (_, ranks, crowding) = Self::rank(&values);
+            let tournament = |rng: &mut fastrand::Rng| {
+                let a = rng.usize(0..population.len());
+                let b = rng.usize(0..population.len());
+                if ranks[a] < ranks[b] || ranks[a] == ranks[b] && crowding[a] > crowding[b] {
+                    a
+                } else {
+                    b
+                }
+            };
+            let mut offspring: Vec<Individual> = (0..self.population_size)
+                .map(|_| {
+                    let a = tournament(&mut rng);
+                    let b = tournament(&mut rng);
+                    let choice = selector.choose(&mut rng);
+                    population[a].offspring(
+                        &population[b],
+                        &choice,
+                        physical_layout,
+                        &mut thread_rng,
+                        &mut rng,
+                    )
+                })
+                .collect();
+            let offspring_values: Vec<Vec<f32>> = offspring
+                .par_iter()
+                .map(|individual| self.values(physical_layout, &individual.layout, tri_grams))
+                .collect();
+
+            // Keep the best fronts of parents and offspring, cutting the last one by crowding
+            population.append(&mut offspring);
+            values.extend(offspring_values);
+            let (fronts, _, crowding) = Self::rank(&values);
+            let improved = fronts[0].iter().any(|index| *index >= self.population_size);
+            let mut selected = Vec::with_capacity(self.population_size);
+            for front in fronts {
+                if selected.len() + front.len() <= self.population_size {
+                    selected.extend(front);
+                    continue;
+                }
+                let mut front = front;
+                front.sort_by(|a, b| {
+                    crowding[*b]
+                        .partial_cmp(&crowding[*a])
+                        .expect("Failed to compare crowding distances")
+                });
+                front.truncate(self.population_size - selected.len());
+                selected.extend(front);
+                break;
+            }
+            let mut slots: Vec<Option<Individual>> = population.into_iter().map(Some).collect();
+            population = selected
+                .iter()
+                .map(|index| slots[*index].take().expect("Failed to select individual"))
+                .collect();
+            values = selected
+                .iter()
+                .map(|index| values[*index].clone())
+                .collect();
+
+            if i % 100 == 0 {
+                println!("iteration: {} / {}", i, options.iterations);
+            }
+
+            count = if improved { 0 } else { count + 1 };
+            if count > options.early_stop_count {
+                println!(
+                    "No improvement for {} iterations, stopping...",
+                    options.early_stop_count
+                );
+                break;
+            }
+        }
+
+        let fronts = non_dominated_sort(&values);
+        let mut layouts: Vec<ParetoLayout> = Vec::new();
+        for index in fronts.into_iter().next().unwrap_or_default() {
+            let layout = &population[index].layout;
+            if layouts
+                .iter()
+                .all(|other| other.layout.slots() != layout.slots())
+            {
+                layouts.push(ParetoLayout {
+                    layout: layout.clone(),
+                    values: values[index].clone(),
+                });
+            }
+        }
+        layouts.sort_by(|a, b| {
+            a.values[0]
+                .partial_cmp(&b.values[0])
+                .expect("Failed to compare objective values")
+        });
+        ParetoFront {
+            objectives: self.objectives.clone(),
+            layouts,
+            iterations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::{sample_physical_layout, LearningCost};
+
+    #[test]
+    fn test_nsga2() {
+        let values = vec![
+            vec![1.0, 4.0],
+            vec![2.0, 2.0],
+            vec![3.0, 3.0],
+            vec![4.0, 1.0],
+            vec![5.0, 5.0],
+        ];
+        assert_eq!(
+            non_dominated_sort(&values),
+            vec![vec![0, 1, 3], vec![2], vec![4]]
+        );
+        let distances = crowding_distance(&values, &[0, 1, 3]);
+        assert_eq!(distances[0], f32::INFINITY);
+        assert_eq!(distances[1], 2.0);
+        assert_eq!(distances[2], f32::INFINITY);
+
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefghij".chars().collect();
+        let initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+            (LogicalNGram::new(['g', 'h', 'i']), 0.2),
+        ]
+        .into_iter()
+        .collect();
+        // a b c d e | f g h i j: c is typed by the left hand, f and i by the right
+        let hand_balance =
+            Objective::HandBalance.value(&physical_layout, &initial_layout, &tri_grams);
+        assert!(hand_balance.abs() < 1e-6);
+        let mut left_heavy = initial_layout.clone();
+        left_heavy.swap(5, 0);
+        // f b c d e | a g h i j: c and f on the left hand, i on the right
+        let hand_balance = Objective::HandBalance.value(&physical_layout, &left_heavy, &tri_grams);
+        assert!((hand_balance - (0.8 - 0.2)).abs() < 1e-6);
+
+        // the learning cost is its own objective and stays out of the effort
+        let mut reference = initial_layout.clone();
+        reference.swap(0, 10);
+        let mono_grams: HashMap<LogicalNGram<1>, f32> =
+            [(LogicalNGram::new(['a']), 1.0)].into_iter().collect();
+        let mut learning_layout = initial_layout.clone();
+        learning_layout.set_learning_cost(LearningCost::new(
+            &physical_layout,
+            &reference,
+            &mono_grams,
+        ));
+        let effort = Objective::Effort.value(&physical_layout, &learning_layout, &tri_grams);
+        let learning = Objective::Learning.value(&physical_layout, &learning_layout, &tri_grams);
+        assert!(learning > 0.0);
+        let plain = Objective::Effort.value(&physical_layout, &initial_layout, &tri_grams);
+        assert!((effort - plain).abs() < 1e-4);
+
+        let objectives = vec![
+            Objective::Effort,
+            Objective::HandBalance,
+            Objective::SameFinger,
+        ];
+        let nsga2 = Nsga2::new(6, objectives);
+        let options = RunOptions {
+            iterations: 5,
+            shuffle: false,
+            early_stop_count: 5,
+            top_k: 1,
+        };
+        let front = nsga2.run(&physical_layout, &initial_layout, &tri_grams, &options);
+        assert!(!front.layouts.is_empty());
+        for a in &front.layouts {
+            let values = nsga2.values(&physical_layout, &a.layout, &tri_grams);
+            // the effort is a parallel sum, so a re-evaluation may differ in the last bits
+            assert!(values
+                .iter()
+                .zip(&a.values)
+                .all(|(value, stored)| (value - stored).abs() < 1e-4));
+            assert!(front
+                .layouts
+                .iter()
+                .all(|b| !dominates(&b.values, &a.values)));
+        }
+
+        let path = std::env::temp_dir().join("test_pareto_front.csv");
+        front.write_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "Effort,HandBalance,SameFinger,layout");
+        assert_eq!(lines.len(), front.layouts.len() + 1);
+    }
+}
@@ -0,0 +1,660 @@
+use rand::rngs::ThreadRng;
+use rand::Rng;
+
+use crate::keyboard_layout::{LogicalLayout, PhysicalLayout, NUM_COLS, NUM_ROWS};
+
+/// Recombination of two parent layouts into a child.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Crossover {
+    /// Takes every cycle of slots from either parent; always keeps the constraints.
+    Cycle,
+    /// Partially mapped crossover: a segment from the first parent, the rest mapped from the
+    /// second one.
+    Pmx,
+    /// Order crossover: a segment from the first parent, the rest in the order of the second one.
+    Order,
+    /// Random slots from the first parent, the rest in the order of the second one.
+    PositionBased,
+}
+
+/// A random change of a layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mutation {
+    /// Swaps two slots.
+    Swap,
+    /// Moves a slot to another one, shifting the slots in between.
+    Insertion,
+    /// Swaps two main rows of a layer.
+    RowSwap,
+    /// Swaps two columns of a layer.
+    ColumnSwap,
+    /// Mirrors the main rows of a layer between the hands.
+    HandMirror,
+    /// Swaps the keys of two fingers of a layer, row by row. Only fingers with as many keys
+    /// are swapped, so no key is left behind.
+    FingerGroupSwap,
+}
+
+/// The weighted crossovers and mutations of the genetic search.
+#[derive(Debug, Clone)]
+pub struct Operators {
+    pub crossovers: Vec<(Crossover, f32)>,
+    pub mutations: Vec<(Mutation, f32)>,
+    /// Probability of mutating a child.
+    pub mutation_rate: f32,
+    /// Replaces the weights and the mutation rate by the credit of recent children. Only the
+    /// genetic search adapts; `Nsga2` rejects it.
+    pub adaptation: Option<Adaptation>,
+}
+
+impl Default for Operators {
+    fn default() -> Self {
+        Self {
+            crossovers: vec![(Crossover::Cycle, 1.0)],
+            mutations: vec![(Mutation::Swap, 1.0)],
+            mutation_rate: 0.75,
+            adaptation: None,
+        }
+    }
+}
+
+impl Operators {
+    /// Panics when no operator can be drawn or a weight or the mutation rate is out of range.
+    pub(crate) fn validate(&self) {
+        if self.crossovers.is_empty() || self.mutations.is_empty() {
+            panic!("operators must not be empty");
+        }
+        let weights = self.crossovers.iter().map(|(_, weight)| *weight);
+        if weights
+            .chain(self.mutations.iter().map(|(_, weight)| *weight))
+            .any(|weight| weight < 0.0)
+        {
+            panic!("operator weights must not be negative");
+        }
+        if !(0.0..=1.0).contains(&self.mutation_rate) {
+            panic!("mutation_rate must be in [0, 1]");
+        }
+    }
+}
+
+/// Adaptive operator selection by probability matching.
+///
+/// Every crossover, mutation and mutation strength keeps a moving average of the credit of the
+/// children it made, which is their relative improvement over the better parent, and is drawn in
+/// proportion to it.
+#[derive(Debug, Clone, Copy)]
+pub struct Adaptation {
+    /// Weight of the newest credit in the moving average.
+    pub learning_rate: f32,
+    /// Lowest probability of every choice, which keeps all of them in use.
+    pub min_probability: f32,
+    /// Largest number of mutations applied to a child.
+    pub max_strength: usize,
+}
+
+impl Default for Adaptation {
+    fn default() -> Self {
+        Self {
+            learning_rate: 0.1,
+            min_probability: 0.05,
+            max_strength: 3,
+        }
+    }
+}
+
+/// The operators applied to a child.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Choice {
+    pub(crate) crossover: Crossover,
+    pub(crate) mutation: Mutation,
+    /// Number of times `mutation` is applied.
+    pub(crate) strength: usize,
+    indices: (usize, usize),
+}
+
+/// Draws the operators of every child and learns from their credit when adaptive.
+#[derive(Debug, Clone)]
+pub(crate) struct OperatorSelector {
+    operators: Operators,
+    crossover_quality: Vec<f32>,
+    mutation_quality: Vec<f32>,
+    strength_quality: Vec<f32>,
+}
+
+/// Summed initial quality of the choices of an adaptive selector, on the scale of the credits,
+/// which are relative improvements.
+const INITIAL_CREDIT: f32 = 0.01;
+
+impl OperatorSelector {
+    pub(crate) fn new(operators: &Operators) -> Self {
+        let max_strength = operators
+            .adaptation
+            .map_or(1, |adaptation| adaptation.max_strength);
+        // the weights are the initial shares; adaptive quality is a moving average of credits
+        let scale = |weights: Vec<f32>| -> Vec<f32> {
+            let total: f32 = weights.iter().sum();
+            match operators.adaptation {
+                Some(_) if total > 0.0 => {
+                    weights.iter().map(|w| INITIAL_CREDIT * w / total).collect()
+                }
+                Some(_) => vec![INITIAL_CREDIT / weights.len() as f32; weights.len()],
+                None => weights,
+            }
+        };
+        Self {
+            operators: operators.clone(),
+            crossover_quality: scale(operators.crossovers.iter().map(|(_, w)| *w).collect()),
+            mutation_quality: scale(operators.mutations.iter().map(|(_, w)| *w).collect()),
+            strength_quality: scale(vec![1.0; max_strength + 1]),
+        }
+    }
+
+    pub(crate) fn choose(&self, rng: &mut fastrand::Rng) -> Choice {
+        let crossover = draw(&self.probabilities(&self.crossover_quality), rng);
+        let mutation = draw(&self.probabilities(&self.mutation_quality), rng);
+        let strength = match self.operators.adaptation {
+            Some(_) => draw(&self.probabilities(&self.strength_quality), rng),
+            None => (rng.f32() < self.operators.mutation_rate) as usize,
+        };
+        Choice {
+            crossover: self.operators.crossovers[crossover].0,
+            mutation: self.operators.mutations[mutation].0,
+            strength,
+            indices: (crossover, mutation),
+        }
+    }
+
+    /// Credits the operators of `choice` with the relative improvement of the child.
+    pub(crate) fn reward(&mut self, choice: &Choice, parent_score: f32, child_score: f32) {
+        let Some(adaptation) = self.operators.adaptation else {
+            return;
+        };
+        let credit = ((parent_score - child_score) / parent_score.abs().max(f32::EPSILON)).max(0.0);
+        let update = |quality: &mut f32| *quality += adaptation.learning_rate * (credit - *quality);
+        update(&mut self.crossover_quality[choice.indices.0]);
+        if choice.strength > 0 {
+            update(&mut self.mutation_quality[choice.indices.1]);
+        }
+        update(&mut self.strength_quality[choice.strength]);
+    }
+
+    /// The probabilities of choices with `quality`, each at least the minimum probability.
+    pub(crate) fn probabilities(&self, quality: &[f32]) -> Vec<f32> {
+        let min = self
+            .operators
+            .adaptation
+            .map_or(0.0, |adaptation| adaptation.min_probability);
+        let total: f32 = quality.iter().sum();
+        let uniform = 1.0 / quality.len() as f32;
+        if total <= 0.0 || min >= uniform {
+            return vec![uniform; quality.len()];
+        }
+        quality
+            .iter()
+            .map(|q| min + (1.0 - quality.len() as f32 * min) * q / total)
+            .collect()
+    }
+}
+
+/// Draws an index in proportion to `weights`.
+fn draw(weights: &[f32], rng: &mut fastrand::Rng) -> usize {
+    let total: f32 = weights.iter().sum();
+    let mut threshold = rng.f32() * total;
+    weights
+        .iter()
+        .position(|weight| {
+            threshold -= weight;
+            threshold < 0.0
+        })
+        .unwrap_or(weights.len() - 1)
+}
+
+/// Crosses the slots of the parents as permutations of the slots of `parent1`, whose empty slots
+/// are told apart by their order. Only the cycle crossover is sure to keep the constraints.
+pub(crate) fn crossover(
+    crossover: Crossover,
+    parent1: &LogicalLayout,
+    parent2: &LogicalLayout,
+    rng: &mut ThreadRng,
+) -> LogicalLayout {
+    let n = parent1.len();
+    let empty_slots: Vec<usize> = (0..n).filter(|i| parent1.get(*i).is_none()).collect();
+    let mut empty_rank = 0;
+    let p1: Vec<usize> = (0..n).collect();
+    let p2: Vec<usize> = (0..n)
+        .map(|i| match parent2.get(i) {
+            Some(c) => parent1.get_char_index(c),
+            None => {
+                empty_rank += 1;
+                empty_slots[empty_rank - 1]
+            }
+        })
+        .collect();
+
+    let a = rng.gen_range(0..n);
+    let b = rng.gen_range(a..=n);
+    let child = match crossover {
+        Crossover::Cycle => {
+            let from_other: Vec<bool> = (0..n).map(|_| rng.gen_bool(0.5)).collect();
+            cycle_crossover(&p1, &p2, &from_other)
+        }
+        Crossover::Pmx => pmx(&p1, &p2, a, b),
+        Crossover::Order => order_crossover(&p1, &p2, a, b),
+        Crossover::PositionBased => {
+            let kept: Vec<bool> = (0..n).map(|_| rng.gen_bool(0.5)).collect();
+            position_based(&p1, &p2, &kept)
+        }
+    };
+
+    let mut layout = parent1.clone();
+    for (slot, id) in child.into_iter().enumerate() {
+        layout.set(slot, parent1.get(id));
+    }
+    layout
+}
+
+/// Every cycle of slots holds the same ids in both parents, so every slot gets the id of the same
+/// slot of a parent. The cycle starting at slot `i` is taken from `p2` when `from_other[i]`.
+fn cycle_crossover(p1: &[usize], p2: &[usize], from_other: &[bool]) -> Vec<usize> {
+    let n = p1.len();
+    let mut position1 = vec![0; n];
+    for (i, id) in p1.iter().enumerate() {
+        position1[*id] = i;
+    }
+    let mut child = p1.to_vec();
+    let mut visited = vec![false; n];
+    for (start, from_other) in from_other.iter().enumerate() {
+        let mut i = start;
+        while !visited[i] {
+            visited[i] = true;
+            if *from_other {
+                child[i] = p2[i];
+            }
+            i = position1[p2[i]];
+        }
+    }
+    child
+}
+
+fn pmx(p1: &[usize], p2: &[usize], a: usize, b: usize) -> Vec<usize> {
+    let n = p1.len();
+    let mut position2 = vec![0; n];
+    for (i, id) in p2.iter().enumerate() {
+        position2[*id] = i;
+    }
+    let mut child = vec![None; n];
+    let mut used = vec![false; n];
+    for i in a..b {
+        child[i] = Some(p1[i]);
+        used[p1[i]] = true;
+    }
+    for i in a..b {
+        if used[p2[i]] {
+            continue;
+        }
+        let mut position = i;
+        while (a..b).contains(&position) {
+            position = position2[p1[position]];
+        }
+        child[position] = Some(p2[i]);
+        used[p2[i]] = true;
+    }
+    child
+        .into_iter()
+        .zip(p2)
+        .map(|(id, other)| id.unwrap_or(*other))
+        .collect()
+}
+
+fn order_crossover(p1: &[usize], p2: &[usize], a: usize, b: usize) -> Vec<usize> {
+    let n = p1.len();
+    let mut child = vec![None; n];
+    let mut used = vec![false; n];
+    for i in a..b {
+        child[i] = Some(p1[i]);
+        used[p1[i]] = true;
+    }
+    let free: Vec<usize> = (b..n).chain(0..b).filter(|i| child[*i].is_none()).collect();
+    let rest = (b..n).chain(0..b).map(|i| p2[i]).filter(|id| !used[*id]);
+    for (i, id) in free.into_iter().zip(rest) {
+        child[i] = Some(id);
+    }
+    child
+        .into_iter()
+        .map(|id| id.expect("Failed to fill the child"))
+        .collect()
+}
+
+fn position_based(p1: &[usize], p2: &[usize], kept: &[bool]) -> Vec<usize> {
+    let n = p1.len();
+    let mut used = vec![false; n];
+    for i in (0..n).filter(|i| kept[*i]) {
+        used[p1[i]] = true;
+    }
+    let mut rest = p2.iter().filter(|id| !used[**id]);
+    (0..n)
+        .map(|i| {
+            if kept[i] {
+                p1[i]
+            } else {
+                *rest.next().expect("Failed to fill the child")
+            }
+        })
+        .collect()
+}
+
+/// Applies `mutation`, undoing it when it breaks the constraints.
+pub(crate) fn mutate(
+    mutation: Mutation,
+    layout: &mut LogicalLayout,
+    physical_layout: &PhysicalLayout,
+    rng: &mut fastrand::Rng,
+) {
+    let layer = rng.usize(0..layout.num_layers());
+    let grid_key = |row: usize, col: usize| row * NUM_COLS + col;
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    match mutation {
+        Mutation::Swap => {
+            pairs.push((rng.usize(0..layout.len()), rng.usize(0..layout.len())));
+        }
+        Mutation::Insertion => {
+            let from = rng.usize(0..layout.len());
+            let to = rng.usize(0..layout.len());
+            if from < to {
+                pairs.extend((from..to).map(|i| (i, i + 1)));
+            } else {
+                pairs.extend((to..from).rev().map(|i| (i, i + 1)));
+            }
+        }
+        Mutation::RowSwap => {
+            let (row1, row2) = (rng.usize(0..NUM_ROWS), rng.usize(0..NUM_ROWS));
+            pairs.extend((0..NUM_COLS).map(|col| (grid_key(row1, col), grid_key(row2, col))));
+        }
+        Mutation::ColumnSwap => {
+            let (col1, col2) = (rng.usize(0..NUM_COLS), rng.usize(0..NUM_COLS));
+            pairs.extend((0..NUM_ROWS).map(|row| (grid_key(row, col1), grid_key(row, col2))));
+        }
+        Mutation::HandMirror => {
+            for row in 0..NUM_ROWS {
+                for col in 0..NUM_COLS / 2 {
+                    pairs.push((grid_key(row, col), grid_key(row, NUM_COLS - 1 - col)));
+                }
+            }
+        }
+        Mutation::FingerGroupSwap => {
+            let group = |key: usize| -> Vec<usize> {
+                let hand = physical_layout.hand(key);
+                let finger = physical_layout.finger(key);
+                (0..NUM_ROWS * NUM_COLS)
+                    .filter(|other| {
+                        physical_layout.hand(*other) == hand
+                            && physical_layout.finger(*other) == finger
+                    })
+                    .collect()
+            };
+            let group1 = group(rng.usize(0..NUM_ROWS * NUM_COLS));
+            // each group once, by its first key
+            let mut partners: Vec<Vec<usize>> = (0..NUM_ROWS * NUM_COLS)
+                .map(group)
+                .enumerate()
+                .filter(|(key, other)| {
+                    other[0] == *key && other.len() == group1.len() && *other != group1
+                })
+                .map(|(_, other)| other)
+                .collect();
+            if !partners.is_empty() {
+                let group2 = partners.swap_remove(rng.usize(0..partners.len()));
+                pairs.extend(group1.into_iter().zip(group2));
+            }
+        }
+    }
+
+    let grid = !matches!(mutation, Mutation::Swap | Mutation::Insertion);
+    let swaps: Vec<(usize, usize)> = pairs
+        .into_iter()
+        .filter_map(|(a, b)| {
+            if grid {
+                layout.grid_slot(layer, a).zip(layout.grid_slot(layer, b))
+            } else {
+                Some((a, b))
+            }
+        })
+        .collect();
+    if let [(a, b)] = swaps[..] {
+        if layout.can_swap(a, b) {
+            layout.swap(a, b);
+        }
+        return;
+    }
+
+    // a chain of swaps may pass through invalid layouts, so only the result is checked
+    for (a, b) in &swaps {
+        layout.swap(*a, *b);
+    }
+    if !layout.is_valid() {
+        for (a, b) in swaps.iter().rev() {
+            layout.swap(*a, *b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::genetic::Individual;
+    use crate::keyboard_layout::{sample_physical_layout, Constraints};
+
+    #[test]
+    fn test_operators() {
+        let physical_layout = sample_physical_layout();
+
+        let from_other = [true, false, false, false, false];
+        assert_eq!(
+            cycle_crossover(&[0, 1, 2, 3, 4], &[1, 0, 3, 4, 2], &from_other),
+            vec![1, 0, 2, 3, 4]
+        );
+        assert_eq!(
+            pmx(&[0, 1, 2, 3, 4], &[3, 4, 0, 1, 2], 1, 3),
+            vec![3, 1, 2, 4, 0]
+        );
+        assert_eq!(
+            order_crossover(&[0, 1, 2, 3, 4], &[3, 0, 4, 1, 2], 1, 3),
+            vec![4, 1, 2, 3, 0]
+        );
+        assert_eq!(
+            position_based(
+                &[0, 1, 2, 3, 4],
+                &[4, 3, 2, 1, 0],
+                &[true, false, true, false, false]
+            ),
+            vec![0, 4, 2, 3, 1]
+        );
+
+        let chars: Vec<char> = "abcdefghijklmnopqrstuv".chars().collect();
+        let mut parent1 = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let mut constraints = Constraints::new();
+        constraints.pin('v', 20).allow('a', [0, 1, 2, 10, 11, 12]);
+        parent1.set_constraints(&constraints);
+        let sorted = |layout: &LogicalLayout| {
+            let mut slots = layout.slots().to_vec();
+            slots.sort();
+            slots
+        };
+        let expected = sorted(&parent1);
+
+        let mut rng = rand::thread_rng();
+        let mut rng_fast = fastrand::Rng::with_seed(3);
+        let mut parent2 = parent1.clone();
+        parent2.shuffle(&mut rng_fast);
+        let crossovers = [
+            Crossover::Cycle,
+            Crossover::Pmx,
+            Crossover::Order,
+            Crossover::PositionBased,
+        ];
+        let (individual1, individual2) =
+            (Individual::new(parent1.clone()), Individual::new(parent2));
+        let mut broken = 0;
+        for kind in crossovers {
+            for _ in 0..20 {
+                let child = crossover(kind, &parent1, &individual2.layout, &mut rng);
+                assert_eq!(sorted(&child), expected);
+                assert!(kind != Crossover::Cycle || child.is_valid());
+                assert!(child
+                    .chars()
+                    .all(|c| child.get(child.get_char_index(c)) == Some(c)));
+                broken += !child.is_valid() as usize;
+
+                // children breaking the constraints are replaced by a cycle crossover
+                let child = individual1.crossover(&individual2, kind, &mut rng);
+                assert_eq!(sorted(&child.layout), expected);
+                assert!(child.layout.is_valid());
+            }
+        }
+        assert!(broken > 0);
+
+        let mutations = [
+            Mutation::Swap,
+            Mutation::Insertion,
+            Mutation::RowSwap,
+            Mutation::ColumnSwap,
+            Mutation::HandMirror,
+            Mutation::FingerGroupSwap,
+        ];
+        let mut layout = parent1.clone();
+        let mut changed = 0;
+        for mutation in mutations {
+            for _ in 0..20 {
+                let before = layout.slots().to_vec();
+                mutate(mutation, &mut layout, &physical_layout, &mut rng_fast);
+                assert_eq!(sorted(&layout), expected);
+                assert!(layout.is_valid());
+                changed += (layout.slots() != before) as usize;
+            }
+        }
+        assert!(changed > 0);
+        // mirroring moves the pinned 'v' off its key, so the layout is restored
+        let before = layout.slots().to_vec();
+        mutate(
+            Mutation::HandMirror,
+            &mut layout,
+            &physical_layout,
+            &mut rng_fast,
+        );
+        assert_eq!(layout.slots(), before);
+
+        let qwerty: Vec<char> = "qwertyuiopasdfghjkl;zxcvbnm,./".chars().collect();
+        let qwerty = LogicalLayout::from_usable_chars(&physical_layout, qwerty);
+        let mut mirrored = qwerty.clone();
+        mutate(
+            Mutation::HandMirror,
+            &mut mirrored,
+            &physical_layout,
+            &mut rng_fast,
+        );
+        assert_eq!(
+            mirrored.layer(0),
+            "poiuytrewq;lkjhgfdsa/.,mnbvcxz".chars().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_finger_group_swap() {
+        let physical_layout = sample_physical_layout();
+        let group = |key: usize| (physical_layout.hand(key), physical_layout.finger(key));
+        let size = |key: usize| {
+            (0..NUM_ROWS * NUM_COLS)
+                .filter(|other| group(*other) == group(key))
+                .count()
+        };
+        // the index fingers have five keys, the middle and ring fingers four, the pinkies two
+        assert_eq!((size(4), size(2), size(0), size(10)), (5, 4, 4, 2));
+
+        let qwerty: Vec<char> = "qwertyuiopasdfghjkl;zxcvbnm,./".chars().collect();
+        let qwerty = LogicalLayout::from_usable_chars(&physical_layout, qwerty);
+        let mut rng = fastrand::Rng::with_seed(7);
+        let mut swapped = 0;
+        for _ in 0..100 {
+            let mut layout = qwerty.clone();
+            mutate(
+                Mutation::FingerGroupSwap,
+                &mut layout,
+                &physical_layout,
+                &mut rng,
+            );
+            let moved: Vec<usize> = (0..NUM_ROWS * NUM_COLS)
+                .filter(|key| layout.get(*key) != qwerty.get(*key))
+                .collect();
+            if moved.is_empty() {
+                continue;
+            }
+            swapped += 1;
+            // two whole groups of the same size trade their keys
+            let mut groups = Vec::new();
+            for key in &moved {
+                if !groups.contains(&group(*key)) {
+                    groups.push(group(*key));
+                }
+            }
+            assert_eq!(groups.len(), 2);
+            let sizes: Vec<usize> = moved.iter().map(|key| size(*key)).collect();
+            assert!(sizes.iter().all(|s| *s == sizes[0]));
+            assert_eq!(moved.len(), 2 * sizes[0]);
+            for key in &moved {
+                let from = qwerty.get_char_index(layout.get(*key).unwrap());
+                assert_eq!(size(from), size(*key));
+                assert_ne!(group(from), group(*key));
+            }
+        }
+        assert!(swapped > 0);
+    }
+
+    #[test]
+    fn test_adaptation() {
+        let operators = Operators {
+            crossovers: vec![(Crossover::Cycle, 1.0), (Crossover::Pmx, 1.0)],
+            mutations: vec![(Mutation::Swap, 3.0), (Mutation::RowSwap, 1.0)],
+            mutation_rate: 0.0,
+            adaptation: None,
+        };
+        let mut rng = fastrand::Rng::with_seed(5);
+        let selector = OperatorSelector::new(&operators);
+        assert_eq!(
+            selector.probabilities(&selector.mutation_quality),
+            vec![0.75, 0.25]
+        );
+        assert!((0..100).all(|_| selector.choose(&mut rng).strength == 0));
+
+        let adaptation = Adaptation {
+            learning_rate: 0.5,
+            min_probability: 0.1,
+            max_strength: 2,
+        };
+        let mut selector = OperatorSelector::new(&Operators {
+            adaptation: Some(adaptation),
+            ..operators
+        });
+        // a single improving child already raises the share of its crossover
+        let initial = selector.probabilities(&selector.crossover_quality);
+        assert!((initial[0] - 0.5).abs() < 1e-6);
+        let mut choice = selector.choose(&mut rng);
+        choice.crossover = Crossover::Pmx;
+        choice.indices.0 = 1;
+        let mut probe = selector.clone();
+        probe.reward(&choice, 10.0, 9.0);
+        assert!(probe.probabilities(&probe.crossover_quality)[1] > 0.6);
+
+        // only PMX children with a single mutation improve on their parents
+        for _ in 0..200 {
+            let choice = selector.choose(&mut rng);
+            let improved = choice.crossover == Crossover::Pmx && choice.strength == 1;
+            let child_score = if improved { 8.0 } else { 12.0 };
+            selector.reward(&choice, 10.0, child_score);
+        }
+        let crossover = selector.probabilities(&selector.crossover_quality);
+        assert!(crossover[1] > 0.8 && crossover[0] >= 0.1 - 1e-6);
+        let strength = selector.probabilities(&selector.strength_quality);
+        assert!(strength[1] > strength[0] && strength[1] > strength[2]);
+        assert!(strength.iter().all(|p| *p >= 0.1 - 1e-6));
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use crate::keyboard_layout::{LogicalLayout, PhysicalLayout};
+use crate::n_gram::{LogicalNGram, NGramDB};
+
+/// Limits and outputs of a single optimization run.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub iterations: usize,
+    /// Start from shuffled copies of the initial layout instead of the layout itself.
+    pub shuffle: bool,
+    /// Stop after this many iterations without improvement.
+    pub early_stop_count: usize,
+    /// Number of distinct best layouts kept in the result.
+    pub top_k: usize,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 1000,
+            shuffle: true,
+            early_stop_count: 100,
+            top_k: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// All iterations were run.
+    Iterations,
+    /// The best score did not improve for `early_stop_count` iterations.
+    NoImprovement,
+    /// No allowed move is left, or none improves the layout.
+    LocalOptimum,
+}
+
+#[derive(Debug, Clone)]
+pub struct OptimizeResult {
+    pub layout: LogicalLayout,
+    pub score: f32,
+    pub iterations: usize,
+    pub stop_reason: StopReason,
+    /// The best score after every iteration.
+    pub history: Vec<f32>,
+    /// The best distinct layouts found, best first, including `layout`.
+    pub top_layouts: Vec<(LogicalLayout, f32)>,
+}
+
+impl OptimizeResult {
+    /// Prints the score, the objective terms and every layer of the best layout.
+    pub fn report(&self, physical_layout: &PhysicalLayout, ngram_db: &NGramDB) {
+        let layout = &self.layout;
+        println!("best score: {}", self.score);
+        let mono_grams = ngram_db
+            .get_mono_grams()
+            .expect("Failed to get mono grams");
+        println!(
+            "off-layout mass: {:.2}%",
+            layout.off_layout_mass(&mono_grams) * 100.0
+        );
+        if let Some(distance) = layout.learning_distance(physical_layout) {
+            println!("learning cost: {}", distance);
+        }
+        for (constraint, penalty) in layout.penalties(physical_layout) {
+            println!("penalty of {:?}: {}", constraint, penalty);
+        }
+        for layer in 0..layout.num_layers() {
+            physical_layout.print(&layout.layer(layer));
+            for (keys, c) in layout.combos(physical_layout, layer) {
+                println!("combo {:?}: {}", keys, c);
+            }
+        }
+    }
+}
+
+/// Keeps the best distinct layouts seen during a run.
+#[derive(Debug, Clone)]
+pub(crate) struct TopLayouts {
+    k: usize,
+    layouts: Vec<(LogicalLayout, f32)>,
+}
+
+impl TopLayouts {
+    pub(crate) fn new(k: usize) -> Self {
+        Self {
+            k,
+            layouts: Vec::with_capacity(k + 1),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, layout: &LogicalLayout, score: f32) {
+        if self.layouts.len() == self.k
+            && self
+                .layouts
+                .last()
+                .is_none_or(|(_, worst)| score >= *worst)
+        {
+            return;
+        }
+        if self
+            .layouts
+            .iter()
+            .any(|(other, _)| other.slots() == layout.slots())
+        {
+            return;
+        }
+        let index = self.layouts.partition_point(|(_, other)| *other <= score);
+        self.layouts.insert(index, (layout.clone(), score));
+        self.layouts.truncate(self.k);
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<(LogicalLayout, f32)> {
+        self.layouts
+    }
+}
+
+/// A search for the best placement of the chars of a layout.
+pub trait Optimizer {
+    /// Optimizes the chars of `initial_layout`, keeping its layers and constraints, for already
+    /// prepared tri-gram frequencies.
+    fn run(
+        &self,
+        physical_layout: &PhysicalLayout,
+        initial_layout: &LogicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        options: &RunOptions,
+    ) -> OptimizeResult;
+}
+
+/// Runs every optimizer from the same layout with the same options and prints their results side
+/// by side.
+pub fn compare(
+    optimizers: &[(&str, &dyn Optimizer)],
+    physical_layout: &PhysicalLayout,
+    initial_layout: &LogicalLayout,
+    tri_grams: &HashMap<LogicalNGram<3>, f32>,
+    options: &RunOptions,
+) -> Vec<OptimizeResult> {
+    let results: Vec<OptimizeResult> = optimizers
+        .iter()
+        .map(|(_, optimizer)| optimizer.run(physical_layout, initial_layout, tri_grams, options))
+        .collect();
+    for ((name, _), result) in optimizers.iter().zip(&results) {
+        println!(
+            "{}: score {} after {} iterations ({:?})",
+            name, result.score, result.iterations, result.stop_reason
+        );
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithms::Genetic;
+    use crate::keyboard_layout::sample_physical_layout;
+
+    #[test]
+    fn test_optimizer() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = "abcdefghij".chars().collect();
+        let initial_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', 'c']), 0.5),
+            (LogicalNGram::new(['d', 'e', 'f']), 0.3),
+            (LogicalNGram::new(['g', 'h', 'i']), 0.2),
+            // 'z' is not on the layout
+            (LogicalNGram::new(['a', 'b', 'z']), 0.1),
+        ]
+        .into_iter()
+        .collect();
+
+        let optimizer: Box<dyn Optimizer> = Box::new(Genetic::new(4, 2));
+        let options = RunOptions {
+            iterations: 20,
+            early_stop_count: 5,
+            top_k: 3,
+            ..Default::default()
+        };
+        let result = optimizer.run(&physical_layout, &initial_layout, &tri_grams, &options);
+
+        assert_eq!(result.history.len(), result.iterations);
+        assert!(result.history.windows(2).all(|w| w[1] <= w[0]));
+        if result.stop_reason == StopReason::Iterations {
+            assert_eq!(result.iterations, 20);
+        }
+        let evaluated = result.layout.evaluate(&physical_layout, &tri_grams);
+        assert!((evaluated - result.score).abs() < 1e-4);
+        assert!(result.score >= 0.1 * physical_layout.off_layout_cost());
+
+        assert!(!result.top_layouts.is_empty() && result.top_layouts.len() <= 3);
+        assert_eq!(result.top_layouts[0].1, result.score);
+        assert!(result.top_layouts.windows(2).all(|w| {
+            w[0].1 <= w[1].1 && w[0].0.slots() != w[1].0.slots()
+        }));
+    }
+}
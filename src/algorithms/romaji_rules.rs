@@ -0,0 +1,205 @@
+use std::collections::{HashMap, HashSet};
+
+use super::genetic::Genetic;
+use super::optimizer::{Optimizer, RunOptions};
+use crate::corpus::{RomajiConverter, Shortcut};
+use crate::keyboard_layout::{LogicalLayout, PhysicalLayout};
+use crate::n_gram::LogicalNGram;
+
+/// Jointly optimizes key placement and the romaji shortcuts of the IME table.
+///
+/// Each round optimizes the layout with `Genetic` for the current shortcuts, then greedily
+/// reassigns shortcuts to keys while the layout is fixed, until no reassignment improves.
+pub struct RomajiRuleSearch {
+    genetic: Genetic,
+    shortcuts: Vec<Shortcut>,
+    keys: Vec<char>,
+    rounds: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RomajiRuleResult {
+    pub layout: LogicalLayout,
+    pub rules: Vec<(Shortcut, char)>,
+    pub score: f32,
+}
+
+impl RomajiRuleSearch {
+    /// `keys` are the chars that may be used as shortcut keys; they must also be usable chars.
+    pub fn new(genetic: Genetic, shortcuts: Vec<Shortcut>, keys: Vec<char>, rounds: usize) -> Self {
+        Self {
+            genetic,
+            shortcuts,
+            keys,
+            rounds,
+        }
+    }
+
+    pub fn optimize(
+        &self,
+        physical_layout: &PhysicalLayout,
+        converter: &RomajiConverter,
+        usable_chars: &[char],
+        kana_tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        iterations: usize,
+        early_stop_count: usize,
+    ) -> RomajiRuleResult {
+        let usable_chars_set: HashSet<char> = usable_chars.iter().cloned().collect();
+        assert!(
+            self.keys.iter().all(|key| usable_chars_set.contains(key)),
+            "shortcut keys must be usable chars"
+        );
+
+        let mut rules: Vec<Option<Shortcut>> = vec![None; self.keys.len()];
+        let mut layout = LogicalLayout::from_usable_chars(physical_layout, usable_chars.to_vec());
+        let mut best: Option<RomajiRuleResult> = None;
+        // the greedy sweeps revisit the same rule sets, so convert the kana tri-grams once per set
+        let mut cache = HashMap::new();
+
+        for round in 0..self.rounds {
+            let round_rules = rules.clone();
+            let tri_grams = self.tri_grams(
+                &mut cache,
+                converter,
+                &rules,
+                kana_tri_grams,
+                &usable_chars_set,
+            );
+            let options = RunOptions {
+                iterations,
+                shuffle: round == 0,
+                early_stop_count,
+                top_k: 1,
+            };
+            let result = self
+                .genetic
+                .run(physical_layout, &layout, tri_grams, &options);
+            let mut score = result.score;
+            layout = result.layout;
+
+            let mut improved = true;
+            while improved {
+                improved = false;
+                for key_index in 0..self.keys.len() {
+                    let options =
+                        std::iter::once(None).chain(self.shortcuts.iter().copied().map(Some));
+                    for option in options {
+                        if rules[key_index] == option {
+                            continue;
+                        }
+                        let mut candidate = rules.clone();
+                        if let Some(used) = candidate
+                            .iter()
+                            .position(|rule| option.is_some() && *rule == option)
+                        {
+                            candidate[used] = candidate[key_index];
+                        }
+                        candidate[key_index] = option;
+                        let tri_grams = self.tri_grams(
+                            &mut cache,
+                            converter,
+                            &candidate,
+                            kana_tri_grams,
+                            &usable_chars_set,
+                        );
+                        let candidate_score = layout.evaluate(physical_layout, tri_grams);
+                        if candidate_score < score {
+                            score = candidate_score;
+                            rules = candidate;
+                            improved = true;
+                        }
+                    }
+                }
+            }
+
+            println!("round: {} / {}", round + 1, self.rounds);
+            println!("score: {}", score);
+
+            if best.as_ref().is_none_or(|best| score < best.score) {
+                best = Some(RomajiRuleResult {
+                    layout: layout.clone(),
+                    rules: self.rules(&rules),
+                    score,
+                });
+            }
+            if rules == round_rules {
+                break;
+            }
+        }
+
+        best.expect("rounds must be greater than 0")
+    }
+
+    fn rules(&self, rules: &[Option<Shortcut>]) -> Vec<(Shortcut, char)> {
+        rules
+            .iter()
+            .zip(&self.keys)
+            .filter_map(|(rule, key)| rule.map(|shortcut| (shortcut, *key)))
+            .collect()
+    }
+
+    fn tri_grams<'a>(
+        &self,
+        cache: &'a mut HashMap<Vec<Option<Shortcut>>, HashMap<LogicalNGram<3>, f32>>,
+        converter: &RomajiConverter,
+        rules: &[Option<Shortcut>],
+        kana_tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        usable_chars: &HashSet<char>,
+    ) -> &'a HashMap<LogicalNGram<3>, f32> {
+        cache.entry(rules.to_vec()).or_insert_with(|| {
+            self.converter(converter, rules)
+                .tri_grams(kana_tri_grams, usable_chars)
+        })
+    }
+
+    fn converter(
+        &self,
+        converter: &RomajiConverter,
+        rules: &[Option<Shortcut>],
+    ) -> RomajiConverter {
+        let mut converter = converter.clone();
+        for (shortcut, key) in self.rules(rules) {
+            converter.add_shortcut(shortcut, key);
+        }
+        converter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::RomajiScheme;
+    use crate::keyboard_layout::sample_physical_layout;
+
+    #[test]
+    fn test_romaji_rule_search() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+        let converter = RomajiConverter::new(RomajiScheme::Kunrei);
+        let usable_chars = vec!['h', 'o', 'n', ';'];
+        // "honnnn" takes two key tri-grams for the last ん, "ho;;" only one
+        let kana_tri_grams: HashMap<LogicalNGram<3>, f32> =
+            [(LogicalNGram::new(['ほ', 'ん', 'ん']), 1.0)]
+                .into_iter()
+                .collect();
+        let shortcut = Shortcut {
+            vowel: None,
+            suffix: 'ん',
+        };
+
+        let search = RomajiRuleSearch::new(Genetic::new(4, 1), vec![shortcut], vec![';'], 2);
+        let result = search.optimize(
+            &physical_layout,
+            &converter,
+            &usable_chars,
+            &kana_tri_grams,
+            5,
+            5,
+        );
+        assert_eq!(result.rules, vec![(shortcut, ';')]);
+
+        let usable_chars_set: HashSet<char> = usable_chars.iter().cloned().collect();
+        let without_rules = converter.tri_grams(&kana_tri_grams, &usable_chars_set);
+        assert!(result.score < result.layout.evaluate(&physical_layout, &without_rules));
+    }
+}
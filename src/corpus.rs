@@ -0,0 +1,5 @@
+pub mod normalize;
+pub mod romaji;
+
+pub use normalize::*;
+pub use romaji::*;
@@ -0,0 +1,352 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::UnicodeNormalization;
+
+use super::romaji::{split_voiced_mark, to_hiragana, RomajiConverter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseMode {
+    Fold,
+    Preserve,
+}
+
+/// Text normalization applied to a corpus before its n-grams are counted.
+///
+/// Chars are processed in this order: NFKC, kana folding, romaji conversion, case folding, boundary detection, whitespace
+/// collapsing, allowed-character filtering and collapsing of repeated chars.
+/// A line break ends a sentence and a blank line ends a document.
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    pub nfkc: bool,
+    /// Folds katakana into hiragana.
+    pub hiragana: bool,
+    /// Splits voiced kana into the base kana and `゛` or `゜`, as typed on direct kana layouts.
+    pub split_voiced_marks: bool,
+    /// Converts kana into the keys typed on a romaji IME.
+    pub romaji: Option<RomajiConverter>,
+    pub case: CaseMode,
+    /// Chars kept in the output. Every char is kept when `None`.
+    pub allowed_chars: Option<HashSet<char>>,
+    pub collapse_whitespace: bool,
+    /// Chars whose consecutive runs are collapsed into a single char.
+    pub collapse_chars: HashSet<char>,
+    pub sentence_terminators: HashSet<char>,
+    pub sentence_marker: Option<char>,
+    pub document_marker: Option<char>,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self {
+            nfkc: true,
+            hiragana: false,
+            split_voiced_marks: false,
+            romaji: None,
+            case: CaseMode::Fold,
+            allowed_chars: None,
+            collapse_whitespace: true,
+            collapse_chars: HashSet::new(),
+            sentence_terminators: ['。', '.', '!', '?', '！', '？'].into_iter().collect(),
+            sentence_marker: None,
+            document_marker: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Boundary {
+    None,
+    Sentence,
+    Document,
+}
+
+/// State carried between successive pieces of the same source.
+#[derive(Debug, Clone)]
+pub(crate) struct NormalizeState {
+    last: Option<char>,
+    pending_space: bool,
+    pending_boundary: Boundary,
+    newlines: usize,
+    kana: String,
+    /// The last starter and the marks after it, held back from NFKC until the marks are complete.
+    unnormalized: String,
+}
+
+impl Default for NormalizeState {
+    fn default() -> Self {
+        Self {
+            last: None,
+            pending_space: false,
+            // the start of a source is a document boundary which needs no marker
+            pending_boundary: Boundary::Document,
+            newlines: 0,
+            kana: String::new(),
+            unnormalized: String::new(),
+        }
+    }
+}
+
+impl Normalizer {
+    /// The normalization done by `python/dataset.py`: lowercase a-z and single spaces.
+    pub fn ascii_lowercase() -> Self {
+        let mut allowed_chars: HashSet<char> = ('a'..='z').collect();
+        allowed_chars.insert(' ');
+        Self {
+            nfkc: false,
+            allowed_chars: Some(allowed_chars),
+            collapse_chars: ['*'].into_iter().collect(),
+            sentence_terminators: HashSet::new(),
+            ..Default::default()
+        }
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        let mut state = NormalizeState::default();
+        let mut out = String::with_capacity(text.len());
+        self.push_str(&mut state, text, &mut out);
+        self.finish(&mut state, &mut out);
+        out
+    }
+
+    /// Normalizes `source` line by line into `dest`.
+    pub fn normalize_file<P: AsRef<Path>>(&self, source: P, dest: P) -> io::Result<()> {
+        let mut reader = BufReader::new(File::open(source)?);
+        let mut writer = BufWriter::new(File::create(dest)?);
+        let mut state = NormalizeState::default();
+        let mut line = String::new();
+        let mut out = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            self.push_str(&mut state, &line, &mut out);
+            writer.write_all(out.as_bytes())?;
+            line.clear();
+            out.clear();
+        }
+        self.finish(&mut state, &mut out);
+        writer.write_all(out.as_bytes())?;
+        writer.flush()
+    }
+
+    pub(crate) fn push_str(&self, state: &mut NormalizeState, text: &str, out: &mut String) {
+        if self.nfkc {
+            // a starter composes with the marks after it, which may only arrive with the next piece
+            state.unnormalized.push_str(text);
+            let split = state
+                .unnormalized
+                .char_indices()
+                .rev()
+                .find(|(_, c)| is_starter(*c))
+                .map_or(0, |(i, _)| i);
+            let held = state.unnormalized.split_off(split);
+            let ready = std::mem::replace(&mut state.unnormalized, held);
+            for c in ready.nfkc() {
+                self.push_kana(state, c, out);
+            }
+        } else {
+            for c in text.chars() {
+                self.push_kana(state, c, out);
+            }
+        }
+    }
+
+    /// Ends the source, which is also the end of a document.
+    pub(crate) fn finish(&self, state: &mut NormalizeState, out: &mut String) {
+        let held = std::mem::take(&mut state.unnormalized);
+        for c in held.nfkc() {
+            self.push_kana(state, c, out);
+        }
+        self.flush_kana(state, out);
+        self.mark(state, Boundary::Document);
+        self.flush_boundary(state, out);
+    }
+
+    fn push_kana(&self, state: &mut NormalizeState, c: char, out: &mut String) {
+        let c = if self.hiragana { to_hiragana(c) } else { c };
+        match split_voiced_mark(c) {
+            Some((base, mark)) if self.split_voiced_marks => {
+                self.push_romaji(state, base, out);
+                self.push_romaji(state, mark, out);
+            }
+            _ => self.push_romaji(state, c, out),
+        }
+    }
+
+    /// Buffers runs of kana, which can only be converted once the run is complete.
+    fn push_romaji(&self, state: &mut NormalizeState, c: char, out: &mut String) {
+        match &self.romaji {
+            Some(romaji) if romaji.is_kana(c) => state.kana.push(c),
+            _ => {
+                self.flush_kana(state, out);
+                self.push_cased(state, c, out);
+            }
+        }
+    }
+
+    fn flush_kana(&self, state: &mut NormalizeState, out: &mut String) {
+        if let Some(romaji) = &self.romaji {
+            if state.kana.is_empty() {
+                return;
+            }
+            let mut converted = String::with_capacity(state.kana.len());
+            romaji.convert_run(&state.kana, &mut converted);
+            state.kana.clear();
+            for c in converted.chars() {
+                self.push_cased(state, c, out);
+            }
+        }
+    }
+
+    fn push_cased(&self, state: &mut NormalizeState, c: char, out: &mut String) {
+        match self.case {
+            CaseMode::Fold if c.is_uppercase() => {
+                for lower in c.to_lowercase() {
+                    self.push_char(state, lower, out);
+                }
+            }
+            _ => self.push_char(state, c, out),
+        }
+    }
+
+    fn push_char(&self, state: &mut NormalizeState, c: char, out: &mut String) {
+        if c == '\n' {
+            state.newlines += 1;
+            let boundary = if state.newlines > 1 {
+                Boundary::Document
+            } else {
+                Boundary::Sentence
+            };
+            self.mark(state, boundary);
+            return;
+        }
+        if c == '\r' {
+            return;
+        }
+        state.newlines = 0;
+
+        if self.sentence_terminators.contains(&c) && self.sentence_marker.is_some() {
+            self.mark(state, Boundary::Sentence);
+            return;
+        }
+
+        if c.is_whitespace() {
+            if self.collapse_whitespace {
+                state.pending_space = true;
+                return;
+            }
+            self.emit(state, ' ', out);
+            return;
+        }
+
+        if self.collapse_chars.contains(&c) && state.last == Some(c) {
+            return;
+        }
+        self.emit(state, c, out);
+    }
+
+    fn mark(&self, state: &mut NormalizeState, boundary: Boundary) {
+        state.pending_space = false;
+        if boundary == Boundary::Document || state.pending_boundary == Boundary::None {
+            state.pending_boundary = boundary;
+        }
+    }
+
+    fn flush_boundary(&self, state: &mut NormalizeState, out: &mut String) {
+        let marker = match state.pending_boundary {
+            Boundary::None => None,
+            Boundary::Sentence => self.sentence_marker,
+            Boundary::Document => self.document_marker.or(self.sentence_marker),
+        };
+        state.pending_boundary = Boundary::None;
+        match marker {
+            Some(marker) if state.last.is_some() && state.last != Some(marker) => {
+                out.push(marker);
+                state.last = Some(marker);
+                state.pending_space = false;
+            }
+            // without markers, boundaries still separate words
+            None if state.last.is_some() => state.pending_space = true,
+            _ => (),
+        }
+    }
+
+    fn emit(&self, state: &mut NormalizeState, c: char, out: &mut String) {
+        if !self.is_allowed(c) {
+            return;
+        }
+        if state.pending_boundary != Boundary::None {
+            self.flush_boundary(state, out);
+        }
+        if state.pending_space && c != ' ' {
+            state.pending_space = false;
+            if self.is_allowed(' ') && state.last.is_some_and(|last| last != ' ') {
+                out.push(' ');
+                state.last = Some(' ');
+            }
+        }
+        if c == ' ' && self.collapse_whitespace && state.last == Some(' ') {
+            return;
+        }
+        out.push(c);
+        state.last = Some(c);
+    }
+
+    fn is_allowed(&self, c: char) -> bool {
+        self.allowed_chars
+            .as_ref()
+            .is_none_or(|allowed_chars| allowed_chars.contains(&c))
+    }
+}
+
+/// Whether NFKC never composes `c` with the chars before it, i.e. its compatibility
+/// decomposition starts with a starter. Halfwidth `ﾞ` is not one as it becomes U+3099.
+fn is_starter(c: char) -> bool {
+    let mut first = None;
+    unicode_normalization::char::decompose_compatible(c, |d| {
+        first.get_or_insert(d);
+    });
+    first.is_none_or(|d| canonical_combining_class(d) == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::RomajiScheme;
+
+    #[test]
+    fn test_normalize() {
+        let normalizer = Normalizer::ascii_lowercase();
+        assert_eq!(
+            normalizer.normalize("  Hello,   World!\n\nFoo  bar  "),
+            "hello world foo bar"
+        );
+
+        let normalizer = Normalizer {
+            case: CaseMode::Preserve,
+            collapse_chars: ['ー'].into_iter().collect(),
+            sentence_marker: Some('\u{1e}'),
+            document_marker: Some('\u{1d}'),
+            ..Default::default()
+        };
+        assert_eq!(
+            normalizer.normalize("ＡＢＣ です。  次の文\n\nすごーーい 文書。"),
+            "ABC です\u{1e}次の文\u{1d}すごーい 文書\u{1d}"
+        );
+
+        let normalizer = Normalizer {
+            nfkc: true,
+            romaji: Some(RomajiConverter::new(RomajiScheme::Kunrei)),
+            ..Normalizer::ascii_lowercase()
+        };
+        assert_eq!(normalizer.normalize("ｷｰﾎﾞｰﾄﾞを  打つ"), "kibodowo tu");
+
+        let normalizer = Normalizer {
+            hiragana: true,
+            split_voiced_marks: true,
+            ..Default::default()
+        };
+        assert_eq!(normalizer.normalize("ｷｰﾎﾞｰﾄﾞでパン"), "きーほ゛ーと゛て゛は゜ん");
+    }
+}
@@ -0,0 +1,459 @@
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::n_gram::LogicalNGram;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomajiScheme {
+    Kunrei,
+    Hepburn,
+}
+
+/// How `ん` is typed when the table has no entry for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NHandling {
+    /// Always `nn`.
+    Double,
+    /// `n`, or `nn` when followed by a vowel, `y`, `n` or nothing.
+    Minimal,
+}
+
+/// How `っ` is typed when the table has no entry for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SokuonHandling {
+    /// Double the first consonant of the following kana, falling back to `xtu`.
+    DoubleConsonant,
+    /// Always `xtu`.
+    Explicit,
+}
+
+/// A family of romaji shortcuts in the style of AZIK.
+///
+/// With a `vowel`, every syllable ending in that vowel followed by `suffix` is typed as the
+/// syllable's consonants followed by the shortcut key, e.g. `かん` as `kz`. Without one, `suffix`
+/// alone is typed with the shortcut key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shortcut {
+    pub vowel: Option<char>,
+    pub suffix: char,
+}
+
+impl Shortcut {
+    /// `ん` on its own, syllables ending in `ん` and the common diphthongs.
+    pub fn common() -> Vec<Shortcut> {
+        let mut shortcuts = vec![Shortcut {
+            vowel: None,
+            suffix: HATSUON,
+        }];
+        for vowel in ['a', 'i', 'u', 'e', 'o'] {
+            shortcuts.push(Shortcut {
+                vowel: Some(vowel),
+                suffix: HATSUON,
+            });
+        }
+        for (vowel, suffix) in [('a', 'い'), ('e', 'い'), ('o', 'う'), ('u', 'う')] {
+            shortcuts.push(Shortcut {
+                vowel: Some(vowel),
+                suffix,
+            });
+        }
+        shortcuts
+    }
+}
+
+const SOKUON: char = 'っ';
+const HATSUON: char = 'ん';
+const SOKUON_FALLBACK: &str = "xtu";
+
+#[rustfmt::skip]
+const KUNREI_TABLE: &[(&str, &str)] = &[
+    ("あ", "a"), ("い", "i"), ("う", "u"), ("え", "e"), ("お", "o"),
+    ("か", "ka"), ("き", "ki"), ("く", "ku"), ("け", "ke"), ("こ", "ko"),
+    ("さ", "sa"), ("し", "si"), ("す", "su"), ("せ", "se"), ("そ", "so"),
+    ("た", "ta"), ("ち", "ti"), ("つ", "tu"), ("て", "te"), ("と", "to"),
+    ("な", "na"), ("に", "ni"), ("ぬ", "nu"), ("ね", "ne"), ("の", "no"),
+    ("は", "ha"), ("ひ", "hi"), ("ふ", "hu"), ("へ", "he"), ("ほ", "ho"),
+    ("ま", "ma"), ("み", "mi"), ("む", "mu"), ("め", "me"), ("も", "mo"),
+    ("や", "ya"), ("ゆ", "yu"), ("よ", "yo"),
+    ("ら", "ra"), ("り", "ri"), ("る", "ru"), ("れ", "re"), ("ろ", "ro"),
+    ("わ", "wa"), ("を", "wo"),
+    ("が", "ga"), ("ぎ", "gi"), ("ぐ", "gu"), ("げ", "ge"), ("ご", "go"),
+    ("ざ", "za"), ("じ", "zi"), ("ず", "zu"), ("ぜ", "ze"), ("ぞ", "zo"),
+    ("だ", "da"), ("ぢ", "di"), ("づ", "du"), ("で", "de"), ("ど", "do"),
+    ("ば", "ba"), ("び", "bi"), ("ぶ", "bu"), ("べ", "be"), ("ぼ", "bo"),
+    ("ぱ", "pa"), ("ぴ", "pi"), ("ぷ", "pu"), ("ぺ", "pe"), ("ぽ", "po"),
+    ("ゔ", "vu"),
+    ("ぁ", "xa"), ("ぃ", "xi"), ("ぅ", "xu"), ("ぇ", "xe"), ("ぉ", "xo"),
+    ("ゃ", "xya"), ("ゅ", "xyu"), ("ょ", "xyo"), ("ゎ", "xwa"),
+    ("しぇ", "sye"), ("ちぇ", "tye"), ("じぇ", "zye"),
+    ("ふぁ", "fa"), ("ふぃ", "fi"), ("ふぇ", "fe"), ("ふぉ", "fo"),
+    ("てぃ", "thi"), ("でぃ", "dhi"), ("とぅ", "twu"), ("どぅ", "dwu"),
+    ("うぃ", "wi"), ("うぇ", "we"),
+    ("ゔぁ", "va"), ("ゔぃ", "vi"), ("ゔぇ", "ve"), ("ゔぉ", "vo"),
+    ("ー", "-"), ("、", ","), ("。", "."), ("「", "["), ("」", "]"), ("・", "/"),
+];
+
+#[rustfmt::skip]
+const HEPBURN_TABLE: &[(&str, &str)] = &[
+    ("し", "shi"), ("ち", "chi"), ("つ", "tsu"), ("ふ", "fu"), ("じ", "ji"),
+    ("しゃ", "sha"), ("しゅ", "shu"), ("しょ", "sho"), ("しぇ", "she"),
+    ("ちゃ", "cha"), ("ちゅ", "chu"), ("ちょ", "cho"), ("ちぇ", "che"),
+    ("じゃ", "ja"), ("じゅ", "ju"), ("じょ", "jo"), ("じぇ", "je"),
+];
+
+/// Kana of the i-column whose combination with small `ゃゅょ` is written as consonant + `y`.
+#[rustfmt::skip]
+const YOON_BASES: &[(char, &str)] = &[
+    ('き', "k"), ('し', "s"), ('ち', "t"), ('に', "n"), ('ひ', "h"), ('み', "m"),
+    ('り', "r"), ('ぎ', "g"), ('じ', "z"), ('ぢ', "d"), ('び', "b"), ('ぴ', "p"),
+];
+
+/// Converts hiragana and katakana into the keys typed on a romaji IME.
+///
+/// Kana are matched longest first against the table; any other char is passed through.
+#[derive(Debug, Clone)]
+pub struct RomajiConverter {
+    table: HashMap<String, String>,
+    kana_chars: HashSet<char>,
+    max_len: usize,
+    pub n_handling: NHandling,
+    pub sokuon: SokuonHandling,
+}
+
+impl RomajiConverter {
+    pub fn new(scheme: RomajiScheme) -> Self {
+        let mut converter = Self {
+            table: HashMap::new(),
+            kana_chars: [SOKUON, HATSUON].into_iter().collect(),
+            max_len: 1,
+            n_handling: NHandling::Double,
+            sokuon: SokuonHandling::DoubleConsonant,
+        };
+        for &(kana, romaji) in KUNREI_TABLE {
+            converter.insert(kana, romaji);
+        }
+        for &(base, consonant) in YOON_BASES {
+            for (small, vowel) in [('ゃ', "ya"), ('ゅ', "yu"), ('ょ', "yo")] {
+                converter.insert(
+                    &format!("{}{}", base, small),
+                    &format!("{}{}", consonant, vowel),
+                );
+            }
+        }
+        if scheme == RomajiScheme::Hepburn {
+            for &(kana, romaji) in HEPBURN_TABLE {
+                converter.insert(kana, romaji);
+            }
+        }
+        converter
+    }
+
+    /// Adds or overrides the keys typed for `kana`.
+    pub fn insert(&mut self, kana: &str, romaji: &str) {
+        let kana: String = kana.chars().map(to_hiragana).collect();
+        self.kana_chars.extend(kana.chars());
+        self.max_len = self.max_len.max(kana.chars().count());
+        self.table.insert(kana, romaji.to_string());
+    }
+
+    /// Loads a user-supplied IME table of `input<TAB>output` lines.
+    ///
+    /// The shortest input is used for every output. Lines with a third column (such as the
+    /// `kk<TAB>っ<TAB>k` doubling rules) are covered by `sokuon` and ignored.
+    pub fn load_ime_table(&mut self, text: &str) {
+        let mut shortest: HashMap<&str, &str> = HashMap::new();
+        let mut order = Vec::new();
+        for line in text.lines() {
+            let columns: Vec<&str> = line.split('\t').collect();
+            if columns.len() < 2 || columns.get(2).is_some_and(|next| !next.is_empty()) {
+                continue;
+            }
+            let (input, output) = (columns[0], columns[1]);
+            if input.is_empty() || output.is_empty() {
+                continue;
+            }
+            match shortest.get(output) {
+                Some(current) if current.chars().count() <= input.chars().count() => (),
+                Some(_) => {
+                    shortest.insert(output, input);
+                }
+                None => {
+                    shortest.insert(output, input);
+                    order.push(output);
+                }
+            }
+        }
+        for output in order {
+            self.insert(output, shortest[output]);
+        }
+    }
+
+    /// Adds the table entries of `shortcut` typed with `key`.
+    pub fn add_shortcut(&mut self, shortcut: Shortcut, key: char) {
+        let Some(vowel) = shortcut.vowel else {
+            self.insert(&shortcut.suffix.to_string(), &key.to_string());
+            return;
+        };
+        let entries: Vec<(String, String)> = self
+            .table
+            .iter()
+            .filter(|(_, romaji)| {
+                romaji.ends_with(vowel) && romaji.chars().all(|c| c.is_ascii_lowercase())
+            })
+            .map(|(kana, romaji)| {
+                let mut keys = romaji.clone();
+                if keys.len() > 1 {
+                    keys.pop();
+                }
+                keys.push(key);
+                (format!("{}{}", kana, shortcut.suffix), keys)
+            })
+            .collect();
+        for (kana, keys) in entries {
+            self.insert(&kana, &keys);
+        }
+    }
+
+    pub fn kana_chars(&self) -> &HashSet<char> {
+        &self.kana_chars
+    }
+
+    pub fn is_kana(&self, c: char) -> bool {
+        self.kana_chars.contains(&to_hiragana(c))
+    }
+
+    pub fn convert(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len() * 2);
+        let mut run = String::new();
+        for c in text.chars() {
+            if self.is_kana(c) {
+                run.push(c);
+            } else {
+                self.convert_run(&run, &mut out);
+                run.clear();
+                out.push(c);
+            }
+        }
+        self.convert_run(&run, &mut out);
+        out
+    }
+
+    /// Converts a run of consecutive kana.
+    pub(crate) fn convert_run(&self, run: &str, out: &mut String) {
+        let units = self.units(run);
+        for (i, (kana, romaji)) in units.iter().enumerate() {
+            if !romaji.is_empty() {
+                out.push_str(romaji);
+                continue;
+            }
+            let next = units[i + 1..]
+                .iter()
+                .find_map(|(_, romaji)| romaji.chars().next());
+            match *kana {
+                SOKUON => match next {
+                    Some(c)
+                        if self.sokuon == SokuonHandling::DoubleConsonant
+                            && c.is_ascii_alphabetic()
+                            && !"aiueon".contains(c) =>
+                    {
+                        out.push(c)
+                    }
+                    _ => out.push_str(SOKUON_FALLBACK),
+                },
+                HATSUON => match next {
+                    Some(c) if self.n_handling == NHandling::Minimal && !"aiueoyn".contains(c) => {
+                        out.push('n')
+                    }
+                    _ => out.push_str("nn"),
+                },
+                kana => out.push(kana),
+            }
+        }
+    }
+
+    /// Splits a run of kana into table entries, longest first. Kana without an entry get empty
+    /// keys.
+    fn units(&self, run: &str) -> Vec<(char, String)> {
+        let chars: Vec<char> = run.chars().map(to_hiragana).collect();
+        let mut units: Vec<(char, String)> = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let matched = (1..=self.max_len.min(chars.len() - i))
+                .rev()
+                .find_map(|len| {
+                    let kana: String = chars[i..i + len].iter().collect();
+                    self.table.get(&kana).map(|romaji| (len, romaji.clone()))
+                });
+            match matched {
+                Some((len, romaji)) => {
+                    units.push((chars[i], romaji));
+                    i += len;
+                }
+                None => {
+                    units.push((chars[i], String::new()));
+                    i += 1;
+                }
+            }
+        }
+        units
+    }
+
+    /// Whether the keys of the last char of `text` depend on the kana after it, as for `っ` typed
+    /// by doubling the next consonant.
+    fn ends_open(&self, text: &str) -> bool {
+        let run_start = text
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| self.is_kana(c))
+            .last()
+            .map_or(text.len(), |(i, _)| i);
+        match self.units(&text[run_start..]).last() {
+            Some((SOKUON, romaji)) => {
+                romaji.is_empty() && self.sokuon == SokuonHandling::DoubleConsonant
+            }
+            Some((HATSUON, romaji)) => romaji.is_empty() && self.n_handling == NHandling::Minimal,
+            _ => false,
+        }
+    }
+
+    /// Turns kana tri-gram frequencies into the frequencies of the typed key tri-grams.
+    ///
+    /// The keys typed for the third kana of every tri-gram are attributed to it, so each key
+    /// tri-gram of the typed stream is counted once. A `っ` or `ん` whose keys depend on the kana
+    /// after it is attributed to the following tri-gram instead. The result is not normalized: it
+    /// is weighted per kana, so tables that need fewer keys get a lower total cost. Key tri-grams
+    /// containing chars outside `usable_chars` are dropped.
+    pub fn tri_grams(
+        &self,
+        kana_tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        usable_chars: &HashSet<char>,
+    ) -> HashMap<LogicalNGram<3>, f32> {
+        kana_tri_grams
+            .par_iter()
+            .fold(HashMap::new, |mut tri_grams, (n_gram, count)| {
+                let text = format!("{}{}{}", n_gram.get(0), n_gram.get(1), n_gram.get(2));
+                if self.ends_open(&text) {
+                    return tri_grams;
+                }
+                let mut prefix_text = format!("{}{}", n_gram.get(0), n_gram.get(1));
+                if self.ends_open(&prefix_text) {
+                    prefix_text = n_gram.get(0).to_string();
+                }
+                let prefix: Vec<char> = self.convert(&prefix_text).chars().collect();
+                let keys: Vec<char> = self.convert(&text).chars().collect();
+                let common = prefix.iter().zip(&keys).take_while(|(a, b)| a == b).count();
+                for window in keys.windows(3).skip(common.saturating_sub(2)) {
+                    if window.iter().all(|c| usable_chars.contains(c)) {
+                        *tri_grams
+                            .entry(LogicalNGram::new([window[0], window[1], window[2]]))
+                            .or_insert(0.0) += *count;
+                    }
+                }
+                tri_grams
+            })
+            .reduce(HashMap::new, |mut a, b| {
+                for (n_gram, count) in b {
+                    *a.entry(n_gram).or_insert(0.0) += count;
+                }
+                a
+            })
+    }
+}
+
+pub fn to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// Splits a voiced kana such as `が` into its base kana and the spacing mark `゛` or `゜`, the way
+/// it is typed on direct kana layouts.
+pub fn split_voiced_mark(c: char) -> Option<(char, char)> {
+    if !matches!(c, 'ぁ'..='ゖ' | 'ァ'..='ヺ') {
+        return None;
+    }
+    let mut decomposed = std::iter::once(c).nfd();
+    let base = decomposed.next()?;
+    match decomposed.next()? {
+        '\u{3099}' => Some((base, '゛')),
+        '\u{309a}' => Some((base, '゜')),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let kunrei = RomajiConverter::new(RomajiScheme::Kunrei);
+        assert_eq!(kunrei.convert("しゃしんをとった。"), "syasinnwototta.");
+        assert_eq!(kunrei.convert("キーボード 1つ"), "ki-bo-do 1tu");
+
+        let mut hepburn = RomajiConverter::new(RomajiScheme::Hepburn);
+        hepburn.n_handling = NHandling::Minimal;
+        hepburn.sokuon = SokuonHandling::Explicit;
+        assert_eq!(hepburn.convert("しゃしんをとった"), "shashinwotoxtuta");
+        assert_eq!(hepburn.convert("きんえん"), "kinnenn");
+
+        let mut azik = RomajiConverter::new(RomajiScheme::Kunrei);
+        azik.load_ime_table("q\tん\nnn\tん\nkk\tっ\tk\nkz\tかん\n");
+        assert_eq!(azik.convert("かんじ"), "kzzi");
+        assert_eq!(azik.convert("ほん"), "hoq");
+    }
+
+    #[test]
+    fn test_shortcuts() {
+        let mut converter = RomajiConverter::new(RomajiScheme::Kunrei);
+        let usable_chars: HashSet<char> = ('a'..='z').chain([';']).collect();
+        let kana_tri_grams: HashMap<LogicalNGram<3>, f32> =
+            [(LogicalNGram::new(['か', 'ん', 'じ']), 1.0)]
+                .into_iter()
+                .collect();
+
+        // "kannzi": the keys of じ close the tri-grams "nnz" and "nzi"
+        let tri_grams = converter.tri_grams(&kana_tri_grams, &usable_chars);
+        assert_eq!(tri_grams.values().sum::<f32>(), 2.0);
+        assert!(tri_grams.contains_key(&LogicalNGram::new(['n', 'z', 'i'])));
+
+        converter.add_shortcut(
+            Shortcut {
+                vowel: Some('a'),
+                suffix: 'ん',
+            },
+            ';',
+        );
+        assert_eq!(converter.convert("かんじ"), "k;zi");
+        assert_eq!(converter.convert("きゃん"), "ky;");
+        let tri_grams = converter.tri_grams(&kana_tri_grams, &usable_chars);
+        assert_eq!(tri_grams.values().sum::<f32>(), 2.0);
+        assert!(tri_grams.contains_key(&LogicalNGram::new(['k', ';', 'z'])));
+    }
+
+    fn count_tri_grams(text: &str) -> HashMap<LogicalNGram<3>, f32> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tri_grams = HashMap::new();
+        for w in chars.windows(3) {
+            *tri_grams
+                .entry(LogicalNGram::new([w[0], w[1], w[2]]))
+                .or_insert(0.0) += 1.0;
+        }
+        tri_grams
+    }
+
+    #[test]
+    fn test_deferred_tri_grams() {
+        let mut converter = RomajiConverter::new(RomajiScheme::Kunrei);
+        converter.n_handling = NHandling::Minimal;
+        let usable_chars: HashSet<char> = ('a'..='z').collect();
+
+        // the keys of っ and ん are only known once the next kana follows
+        let tri_grams = converter.tri_grams(&count_tri_grams("、あかった"), &usable_chars);
+        assert_eq!(tri_grams, count_tri_grams("akatta"));
+        let tri_grams = converter.tri_grams(&count_tri_grams("、かんだ"), &usable_chars);
+        assert_eq!(tri_grams, count_tri_grams("kanda"));
+    }
+}
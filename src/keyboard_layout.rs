@@ -1,7 +1,11 @@
+pub mod constraints;
 pub mod hand_model;
+pub mod learning_cost;
 pub mod logical_layout;
 pub mod physical_layout;
 
+pub use constraints::{Constraints, SoftConstraint, SoftConstraints};
 pub use hand_model::*;
-pub use logical_layout::LogicalLayout;
+pub use learning_cost::LearningCost;
+pub use logical_layout::{LogicalLayout, Modifier, Shift, MAGIC_KEY, REPEAT_KEY};
 pub use physical_layout::*;
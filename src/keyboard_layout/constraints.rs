@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+
+use super::hand_model::Hand;
+use super::logical_layout::LogicalLayout;
+use super::physical_layout::PhysicalLayout;
+use super::NUM_COLS;
+
+/// Restrictions on the keys a char may be placed on, which every layout of the search satisfies.
+///
+/// Keys are physical keys. Allowed and forbidden keys apply on every layer, while a pinned char
+/// stays on the base layer.
+#[derive(Debug, Clone, Default)]
+pub struct Constraints {
+    pinned: HashMap<char, usize>,
+    allowed: HashMap<char, HashSet<usize>>,
+    forbidden: HashMap<char, HashSet<usize>>,
+}
+
+impl Constraints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps `c` on `key`, such as Z/X/C/V for shortcuts.
+    pub fn pin(&mut self, c: char, key: usize) -> &mut Self {
+        self.pinned.insert(c, key);
+        self
+    }
+
+    /// Places `c` only on `keys`, such as punctuation on the right hand.
+    pub fn allow<I: IntoIterator<Item = usize>>(&mut self, c: char, keys: I) -> &mut Self {
+        self.allowed.entry(c).or_default().extend(keys);
+        self
+    }
+
+    /// Never places `c` on `keys`.
+    pub fn forbid<I: IntoIterator<Item = usize>>(&mut self, c: char, keys: I) -> &mut Self {
+        self.forbidden.entry(c).or_default().extend(keys);
+        self
+    }
+
+    /// The chars with a constraint.
+    pub fn chars(&self) -> HashSet<char> {
+        self.pinned
+            .keys()
+            .chain(self.allowed.keys())
+            .chain(self.forbidden.keys())
+            .copied()
+            .collect()
+    }
+
+    pub fn allows(&self, c: char, key: usize, layer: usize) -> bool {
+        if let Some(pinned) = self.pinned.get(&c) {
+            if *pinned != key || layer != 0 {
+                return false;
+            }
+        }
+        if let Some(allowed) = self.allowed.get(&c) {
+            if !allowed.contains(&key) {
+                return false;
+            }
+        }
+        self.forbidden
+            .get(&c)
+            .is_none_or(|forbidden| !forbidden.contains(&key))
+    }
+}
+
+/// A preference on the placement of chars, added to the objective as a weighted penalty.
+///
+/// Chars that are not on the layout never violate a constraint.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SoftConstraint {
+    /// All the chars on one hand; costs the number of chars on the other hand.
+    SameHand(Vec<char>),
+    /// All the chars on one row; costs the number of chars off their most common row.
+    SameRow(Vec<char>),
+    /// The first char directly left of the second one on the same row.
+    Adjacent(char, char),
+    /// The two chars on mirrored keys of the two hands, like brackets.
+    Mirrored(char, char),
+    /// The two chars typed with different fingers.
+    DifferentFingers(char, char),
+}
+
+impl SoftConstraint {
+    pub fn violation(&self, physical_layout: &PhysicalLayout, layout: &LogicalLayout) -> f32 {
+        let key = |c: &char| layout.key(*c);
+        let coord = |c: &char| key(c).and_then(|key| physical_layout.coord(key));
+        match self {
+            SoftConstraint::SameHand(chars) => {
+                let hands: Vec<Hand> = chars
+                    .iter()
+                    .filter_map(key)
+                    .map(|key| physical_layout.hand(key))
+                    .collect();
+                let left = hands.iter().filter(|hand| **hand == Hand::Left).count();
+                let right = hands.iter().filter(|hand| **hand == Hand::Right).count();
+                left.min(right) as f32
+            }
+            SoftConstraint::SameRow(chars) => {
+                let rows: Vec<usize> =
+                    chars.iter().filter_map(coord).map(|(row, _)| row).collect();
+                let most_common = rows
+                    .iter()
+                    .map(|row| rows.iter().filter(|other| *other == row).count())
+                    .max()
+                    .unwrap_or(0);
+                (rows.len() - most_common) as f32
+            }
+            SoftConstraint::Adjacent(a, b) => match (coord(a), coord(b)) {
+                (Some((row_a, col_a)), Some((row_b, col_b))) => {
+                    (row_a != row_b || col_a + 1 != col_b) as u8 as f32
+                }
+                _ => 0.0,
+            },
+            SoftConstraint::Mirrored(a, b) => match (coord(a), coord(b)) {
+                (Some((row_a, col_a)), Some((row_b, col_b))) => {
+                    (row_a != row_b || col_a + col_b != NUM_COLS - 1) as u8 as f32
+                }
+                _ => 0.0,
+            },
+            SoftConstraint::DifferentFingers(a, b) => match (key(a), key(b)) {
+                (Some(key_a), Some(key_b))
+                    if physical_layout.hand(key_a) == physical_layout.hand(key_b) =>
+                {
+                    let finger_a = physical_layout.finger(key_a).unwrap_or_default();
+                    let finger_b = physical_layout.finger(key_b).unwrap_or_default();
+                    finger_a.intersects(finger_b) as u8 as f32
+                }
+                _ => 0.0,
+            },
+        }
+    }
+}
+
+/// Weighted soft constraints whose penalties are added to the cost of a layout.
+#[derive(Debug, Clone, Default)]
+pub struct SoftConstraints {
+    terms: Vec<(SoftConstraint, f32)>,
+}
+
+impl SoftConstraints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, constraint: SoftConstraint, weight: f32) -> &mut Self {
+        self.terms.push((constraint, weight));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// The weighted penalty of every constraint, in the order they were added.
+    pub fn penalties(
+        &self,
+        physical_layout: &PhysicalLayout,
+        layout: &LogicalLayout,
+    ) -> Vec<(&SoftConstraint, f32)> {
+        self.terms
+            .iter()
+            .map(|(constraint, weight)| {
+                (constraint, weight * constraint.violation(physical_layout, layout))
+            })
+            .collect()
+    }
+
+    pub fn penalty(&self, physical_layout: &PhysicalLayout, layout: &LogicalLayout) -> f32 {
+        self.penalties(physical_layout, layout)
+            .iter()
+            .map(|(_, penalty)| penalty)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::*;
+
+    #[test]
+    fn test_constraints() {
+        let physical_layout = sample_physical_layout();
+
+        let mut constraints = Constraints::new();
+        constraints
+            .pin('z', 20)
+            .pin('x', 21)
+            .allow('.', get_right_keys())
+            .allow(',', get_right_keys())
+            .forbid('a', [0, 10, 20]);
+        assert!(constraints.allows('z', 20, 0));
+        assert!(!constraints.allows('z', 20, 1));
+        assert!(!constraints.allows('.', 0, 0));
+        assert!(constraints.allows('e', 0, 0));
+
+        let chars: Vec<char> = ('a'..='z').chain(['.', ',']).collect();
+        let mut logical_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        logical_layout.set_constraints(&constraints);
+        assert_eq!(logical_layout.get(20), Some('z'));
+        assert_eq!(logical_layout.get(21), Some('x'));
+        assert_eq!(logical_layout.get(2), Some('c'));
+        assert!(!logical_layout.can_swap(20, 2));
+
+        let mut rng = fastrand::Rng::with_seed(7);
+        for _ in 0..20 {
+            logical_layout.shuffle(&mut rng);
+            assert!(logical_layout.is_valid());
+            assert_eq!(logical_layout.get(20), Some('z'));
+            assert!(get_right_keys().contains(&logical_layout.get_char_index('.')));
+            assert_ne!(logical_layout.get_char_index('a') % NUM_COLS, 0);
+        }
+    }
+
+    #[test]
+    fn test_soft_constraints() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        // q w e r t | y u i o p
+        // a s d f g | h j k l ;
+        // z x c v b | n m , . (
+        let chars: Vec<char> = "qwertyuiopasdfghjkl;zxcvbnm,.(".chars().collect();
+        let mut logical_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        let mut soft_constraints = SoftConstraints::new();
+        soft_constraints
+            .add(SoftConstraint::SameHand("aeiou".chars().collect()), 1.0)
+            .add(SoftConstraint::SameRow(vec![',', '.', ';']), 2.0)
+            .add(SoftConstraint::Adjacent(',', '.'), 1.0)
+            .add(SoftConstraint::Mirrored('(', ')'), 1.0)
+            .add(SoftConstraint::Mirrored('x', '('), 1.0)
+            .add(SoftConstraint::DifferentFingers('d', 'e'), 3.0);
+        let penalties: Vec<f32> = soft_constraints
+            .penalties(&physical_layout, &logical_layout)
+            .into_iter()
+            .map(|(_, penalty)| penalty)
+            .collect();
+        assert_eq!(penalties, vec![2.0, 2.0, 0.0, 0.0, 1.0, 3.0]);
+
+        let tri_grams: HashMap<crate::n_gram::LogicalNGram<3>, f32> = HashMap::new();
+        logical_layout.set_soft_constraints(soft_constraints);
+        assert_eq!(logical_layout.evaluate(&physical_layout, &tri_grams), 8.0);
+    }
+}
@@ -1,6 +1,6 @@
 use bitflags::bitflags;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Hand {
     Left,
     Right,
@@ -15,11 +15,12 @@ impl Hand {
 }
 
 bitflags! {
-    #[derive(Default, Debug, Clone, Copy)]
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
     pub struct Finger: u8 {
         const I = 0b0001;
         const M = 0b0010;
         const R = 0b0100;
         const P = 0b1000;
+        const T = 0b10000;
     }
 }
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use super::hand_model::Hand;
+use super::logical_layout::LogicalLayout;
+use super::physical_layout::PhysicalLayout;
+use super::Finger;
+use crate::n_gram::LogicalNGram;
+
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    key: usize,
+    finger: Finger,
+    hand: Hand,
+}
+
+/// The effort of relearning a layout from a reference layout such as QWERTY.
+///
+/// Every char of the reference costs its frequency times the weights of the changes of its key,
+/// finger and hand. A char that is no longer on the layout changes all three.
+#[derive(Debug, Clone)]
+pub struct LearningCost {
+    reference: HashMap<char, Placement>,
+    frequencies: HashMap<char, f32>,
+    pub moved_weight: f32,
+    pub finger_weight: f32,
+    pub hand_weight: f32,
+    /// Weight of the distance in the objective.
+    pub weight: f32,
+    /// Distance allowed without the over-budget penalty.
+    pub budget: Option<f32>,
+    /// Weight of the distance exceeding the budget.
+    pub over_budget_weight: f32,
+}
+
+impl LearningCost {
+    pub fn new(
+        physical_layout: &PhysicalLayout,
+        reference: &LogicalLayout,
+        mono_grams: &HashMap<LogicalNGram<1>, f32>,
+    ) -> Self {
+        Self {
+            reference: Self::placements(physical_layout, reference),
+            frequencies: mono_grams
+                .iter()
+                .map(|(n_gram, frequency)| (n_gram.get(0), *frequency))
+                .collect(),
+            moved_weight: 1.0,
+            finger_weight: 1.0,
+            hand_weight: 1.0,
+            weight: 1.0,
+            budget: None,
+            over_budget_weight: 1000.0,
+        }
+    }
+
+    fn placements(
+        physical_layout: &PhysicalLayout,
+        layout: &LogicalLayout,
+    ) -> HashMap<char, Placement> {
+        layout
+            .chars()
+            .filter_map(|c| {
+                let key = layout.key(c)?;
+                let placement = Placement {
+                    key,
+                    finger: physical_layout.finger(key).unwrap_or_default(),
+                    hand: physical_layout.hand(key),
+                };
+                Some((c, placement))
+            })
+            .collect()
+    }
+
+    /// The frequency-weighted changes from the reference layout.
+    pub fn distance(&self, physical_layout: &PhysicalLayout, layout: &LogicalLayout) -> f32 {
+        self.reference
+            .iter()
+            .map(|(c, reference)| {
+                let frequency = self.frequencies.get(c).copied().unwrap_or(0.0);
+                let changes = match layout.key(*c) {
+                    Some(key) => {
+                        let moved = (key != reference.key) as u8 as f32;
+                        let finger = physical_layout.finger(key).unwrap_or_default();
+                        let finger_changed = (finger != reference.finger) as u8 as f32;
+                        let hand_changed =
+                            (physical_layout.hand(key) != reference.hand) as u8 as f32;
+                        moved * self.moved_weight
+                            + finger_changed * self.finger_weight
+                            + hand_changed * self.hand_weight
+                    }
+                    None => self.moved_weight + self.finger_weight + self.hand_weight,
+                };
+                frequency * changes
+            })
+            .sum()
+    }
+
+    /// The term added to the objective for `layout`.
+    pub fn penalty(&self, physical_layout: &PhysicalLayout, layout: &LogicalLayout) -> f32 {
+        let distance = self.distance(physical_layout, layout);
+        let over_budget = self
+            .budget
+            .map_or(0.0, |budget| (distance - budget).max(0.0));
+        self.weight * distance + self.over_budget_weight * over_budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyboard_layout::*;
+
+    #[test]
+    fn test_learning_cost() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let qwerty: Vec<char> = "qwertyuiopasdfghjkl;zxcvbnm,./".chars().collect();
+        let reference = LogicalLayout::from_usable_chars(&physical_layout, qwerty);
+        let mono_grams: HashMap<LogicalNGram<1>, f32> = [
+            (LogicalNGram::new(['q']), 0.2),
+            (LogicalNGram::new(['w']), 0.3),
+            (LogicalNGram::new(['p']), 0.5),
+        ]
+        .into_iter()
+        .collect();
+        let mut learning_cost = LearningCost::new(&physical_layout, &reference, &mono_grams);
+        assert_eq!(learning_cost.distance(&physical_layout, &reference), 0.0);
+
+        // q and w share the ring finger, while q and p keep the finger but change hands
+        let mut layout = reference.clone();
+        layout.swap(0, 1);
+        assert!((learning_cost.distance(&physical_layout, &layout) - 0.5).abs() < 1e-6);
+        let mut layout = reference.clone();
+        layout.swap(0, 9);
+        assert!((learning_cost.distance(&physical_layout, &layout) - 1.4).abs() < 1e-6);
+
+        learning_cost.weight = 0.5;
+        learning_cost.budget = Some(1.0);
+        learning_cost.over_budget_weight = 10.0;
+        layout.set_learning_cost(learning_cost);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = HashMap::new();
+        assert!((layout.evaluate(&physical_layout, &tri_grams) - 4.7).abs() < 1e-4);
+    }
+}
@@ -1,59 +1,581 @@
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
+use super::constraints::{Constraints, SoftConstraint, SoftConstraints};
+use super::learning_cost::LearningCost;
 use super::physical_layout::PhysicalLayout;
+use super::{NUM_COLS, NUM_ROWS};
 use crate::n_gram::{LogicalNGram, PhysicalNGram};
+
+/// A key that gives access to a layer other than the base layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    /// Held while the key is pressed, like shift. Consecutive chars on the same layer share one
+    /// press.
+    Held(usize),
+    /// Pressed and released before every key of the layer, like one-shot layer keys or the prefix
+    /// keys of 月配列.
+    Prefix(usize),
+    /// Pressed together with every key of the layer, like the thumb keys of NICOLA. The chord of
+    /// the modifier and each key must be added to the physical layout.
+    Simultaneous(usize),
+}
+
+impl Modifier {
+    pub fn key(&self) -> usize {
+        match self {
+            Modifier::Held(key) | Modifier::Prefix(key) | Modifier::Simultaneous(key) => *key,
+        }
+    }
+}
+
+/// Placed on the layout like a char, types the previous char again.
+pub const REPEAT_KEY: char = '⟳';
+/// Placed on the layout like a char, types the output of the magic rule of the previous char.
+pub const MAGIC_KEY: char = '★';
+
+/// A shift key that types the shifted form of the chars on the layout, such as capitals.
+#[derive(Debug, Clone)]
+pub struct Shift {
+    pub key: usize,
+    /// Maps every shifted char to the char typed while shift is held.
+    pub pairs: HashMap<char, char>,
+}
+
+impl Shift {
+    /// Capital letters and the shifted symbols of the US layout.
+    pub fn us(key: usize) -> Self {
+        let mut pairs: HashMap<char, char> = ('a'..='z').map(|c| (c.to_ascii_uppercase(), c)).collect();
+        for (shifted, base) in "!@#$%^&*()_+{}|:\"<>?~".chars().zip("1234567890-=[]\\;',./`".chars()) {
+            pairs.insert(shifted, base);
+        }
+        Self { key, pairs }
+    }
+}
+
+/// The key pushed by `LogicalLayout::keystrokes` for a char that is not on the layout.
+const OFF_LAYOUT: usize = usize::MAX;
+
 #[derive(Debug, Clone)]
 pub struct LogicalLayout {
-    layout: Vec<char>,
+    /// The char of every slot; slots without a char stay empty.
+    layout: Vec<Option<char>>,
     usable_chars: HashMap<char, usize>,
+    /// Physical keys that carry a char on every layer, in slot order.
+    positions: Vec<usize>,
+    /// Modifiers of the layers after the base layer.
+    modifiers: Vec<Modifier>,
+    /// The physical key pressed for each slot, which is a chord on simultaneous layers.
+    slot_keys: Vec<usize>,
+    /// Extra cost of every modifier press, per layer after the base layer.
+    switch_costs: Vec<f32>,
+    shift: Option<Arc<Shift>>,
+    /// Maps a previous char to the char typed by `MAGIC_KEY` after it.
+    magic_rules: Arc<HashMap<char, char>>,
+    /// Chars typed with a dedicated key outside the slots, such as space on a thumb key.
+    fixed_keys: Arc<HashMap<char, usize>>,
+    /// The slots each constrained char may be placed on.
+    allowed_slots: Arc<HashMap<char, Vec<bool>>>,
+    soft_constraints: Arc<SoftConstraints>,
+    learning_cost: Option<Arc<LearningCost>>,
 }
 
 impl LogicalLayout {
     pub fn from_usable_chars(physical_layout: &PhysicalLayout, usable_chars: Vec<char>) -> Self {
-        let mut layout: Vec<char> = usable_chars.to_vec();
-        let mut usable_chars: HashMap<char, usize> = usable_chars
+        Self::with_layers(physical_layout, usable_chars, Vec::new())
+    }
+
+    /// Creates a layout with a base layer and one layer per modifier.
+    ///
+    /// Modifier keys carry no char. Every other position of the physical layout has one slot per
+    /// layer; slot `i` is on layer `i / positions` and `usable_chars` fill the slots in order,
+    /// leaving the remaining slots empty.
+    pub fn with_layers(
+        physical_layout: &PhysicalLayout,
+        usable_chars: Vec<char>,
+        modifiers: Vec<Modifier>,
+    ) -> Self {
+        let positions: Vec<usize> = physical_layout
+            .positions()
             .into_iter()
-            .enumerate()
-            .map(|(i, c)| (c, i))
+            .filter(|key| modifiers.iter().all(|modifier| modifier.key() != *key))
             .collect();
-        let mut next_char = ' ';
-
-        while layout.len() < physical_layout.len() {
-            while usable_chars.contains_key(&next_char) {
-                next_char = ((next_char as u8) + 1) as char;
+        let mut slot_keys = positions.clone();
+        for modifier in &modifiers {
+            for key in &positions {
+                slot_keys.push(match modifier {
+                    Modifier::Simultaneous(thumb) => {
+                        let mut chord = vec![*thumb];
+                        chord.extend(physical_layout.chord_keys(*key).unwrap_or(&[*key]));
+                        physical_layout
+                            .chord(&chord)
+                            .expect("Chords of simultaneous modifiers must be added")
+                    }
+                    _ => *key,
+                });
             }
-            layout.push(next_char);
-            usable_chars.insert(next_char, layout.len() - 1);
         }
+        assert!(
+            usable_chars.len() <= slot_keys.len(),
+            "There are more usable chars than key slots"
+        );
+
+        let mut layout: Vec<Option<char>> = usable_chars.iter().copied().map(Some).collect();
+        layout.resize(slot_keys.len(), None);
+        let usable_chars: HashMap<char, usize> = usable_chars
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| (c, i))
+            .collect();
         LogicalLayout {
             layout,
             usable_chars,
+            positions,
+            switch_costs: vec![0.0; modifiers.len()],
+            modifiers,
+            slot_keys,
+            shift: None,
+            magic_rules: Arc::new(HashMap::new()),
+            fixed_keys: Arc::new(HashMap::new()),
+            allowed_slots: Arc::new(HashMap::new()),
+            soft_constraints: Arc::new(SoftConstraints::new()),
+            learning_cost: None,
         }
     }
 
+    /// The cost of typing `tri_grams` plus the penalties of the soft constraints and the learning
+    /// cost. A char that is not on the layout costs the off-layout cost of `physical_layout`
+    /// instead of its keystrokes.
     pub fn evaluate(
         &self,
         physical_layout: &PhysicalLayout,
         tri_grams: &HashMap<LogicalNGram<3>, f32>,
+    ) -> f32 {
+        self.effort(physical_layout, tri_grams) + self.learning_penalty(physical_layout)
+    }
+
+    /// The cost of typing `tri_grams` plus the penalties of the soft constraints, leaving out the
+    /// learning cost.
+    pub fn effort(
+        &self,
+        physical_layout: &PhysicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
     ) -> f32 {
         let cost = tri_grams
             .par_iter()
-            .map(|(n_gram, score)| -> f32 {
-                let physical_n_gram = PhysicalNGram::new([
-                    self.get_char_index(n_gram.get(0)),
-                    self.get_char_index(n_gram.get(1)),
-                    self.get_char_index(n_gram.get(2)),
-                ]);
-                *score * physical_layout.get_tri_gram_cost(&physical_n_gram)
+            .map_init(Vec::new, |keys, (n_gram, score)| -> f32 {
+                *score * self.tri_gram_cost(physical_layout, n_gram, keys)
+            })
+            .sum::<f32>();
+        cost + self.soft_constraints.penalty(physical_layout, self)
+    }
+
+    /// The cost of typing `n_gram` once, using `keys` as scratch space.
+    pub(crate) fn tri_gram_cost(
+        &self,
+        physical_layout: &PhysicalLayout,
+        n_gram: &LogicalNGram<3>,
+        keys: &mut Vec<usize>,
+    ) -> f32 {
+        keys.clear();
+        let [c0, c1, c2] = self.rewrite(n_gram);
+        self.keystrokes(c0, None, keys);
+        self.keystrokes(c1, Some(c0), keys);
+        let start = keys.len();
+        self.keystrokes(c2, Some(c1), keys);
+        if keys[start..].contains(&OFF_LAYOUT) {
+            return physical_layout.off_layout_cost();
+        }
+
+        // only the key tri-grams closed by the last char belong to this tri-gram
+        let stroke_cost = (start.max(2)..keys.len())
+            .filter(|i| !keys[i - 2..=*i].contains(&OFF_LAYOUT))
+            .map(|i| {
+                let physical_n_gram = PhysicalNGram::new([keys[i - 2], keys[i - 1], keys[i]]);
+                physical_layout.get_tri_gram_cost(&physical_n_gram)
+            })
+            .sum::<f32>();
+        stroke_cost + self.switch_cost(c2, c1)
+    }
+
+    /// The chars whose slots decide the cost of `n_gram`.
+    pub(crate) fn tri_gram_chars(&self, n_gram: &LogicalNGram<3>) -> Vec<char> {
+        let mut chars = Vec::with_capacity(6);
+        for c in self.rewrite(n_gram) {
+            chars.push(c);
+            chars.extend(self.unshifted(c));
+        }
+        chars.sort_unstable();
+        chars.dedup();
+        chars
+    }
+
+    /// The penalties of the soft constraints and the learning cost.
+    pub(crate) fn penalty(&self, physical_layout: &PhysicalLayout) -> f32 {
+        self.soft_constraints.penalty(physical_layout, self) + self.learning_penalty(physical_layout)
+    }
+
+    fn learning_penalty(&self, physical_layout: &PhysicalLayout) -> f32 {
+        self.learning_cost
+            .as_ref()
+            .map_or(0.0, |learning_cost| learning_cost.penalty(physical_layout, self))
+    }
+
+    /// Replaces the chars of `n_gram` typed with `REPEAT_KEY` or `MAGIC_KEY`, when they are placed
+    /// on the layout. The first char has no previous char and is never replaced.
+    fn rewrite(&self, n_gram: &LogicalNGram<3>) -> [char; 3] {
+        let chars = [n_gram.get(0), n_gram.get(1), n_gram.get(2)];
+        let mut rewritten = chars;
+        for i in 1..3 {
+            if chars[i] == chars[i - 1] && self.usable_chars.contains_key(&REPEAT_KEY) {
+                rewritten[i] = REPEAT_KEY;
+            } else if self.magic_rules.get(&chars[i - 1]) == Some(&chars[i])
+                && self.usable_chars.contains_key(&MAGIC_KEY)
+            {
+                rewritten[i] = MAGIC_KEY;
+            }
+        }
+        rewritten
+    }
+
+    pub fn set_magic_rules(&mut self, rules: HashMap<char, char>) {
+        self.magic_rules = Arc::new(rules);
+    }
+
+    pub fn magic_rules(&self) -> &HashMap<char, char> {
+        &self.magic_rules
+    }
+
+    /// The share of `mono_grams` that cannot be typed on the layout.
+    pub fn off_layout_mass(&self, mono_grams: &HashMap<LogicalNGram<1>, f32>) -> f32 {
+        let typeable_chars = self.typeable_chars();
+        let total = mono_grams.values().sum::<f32>();
+        let off_layout = mono_grams
+            .iter()
+            .filter(|(n_gram, _)| !typeable_chars.contains(&n_gram.get(0)))
+            .map(|(_, score)| score)
+            .sum::<f32>();
+        if total > 0.0 {
+            off_layout / total
+        } else {
+            0.0
+        }
+    }
+
+    /// Appends the physical keys pressed to type `c` after `prev`.
+    pub fn keystrokes(&self, c: char, prev: Option<char>, keys: &mut Vec<usize>) {
+        if let Some(key) = self.fixed_keys.get(&c) {
+            keys.push(*key);
+            return;
+        }
+        let index = self.get_char_index(c);
+        if index >= self.layout.len() {
+            match (&self.shift, self.unshifted(c)) {
+                (Some(shift), Some(base)) => {
+                    // shift stays held over consecutive shifted chars
+                    if prev.and_then(|p| self.unshifted(p)).is_none() {
+                        keys.push(shift.key);
+                    }
+                    self.keystrokes(base, None, keys);
+                }
+                _ => keys.push(OFF_LAYOUT),
+            }
+            return;
+        }
+        if let Some(Modifier::Held(key) | Modifier::Prefix(key)) = self.pressed_modifier(c, prev) {
+            keys.push(key);
+        }
+        keys.push(self.slot_keys[index]);
+    }
+
+    /// Restricts the slots of the chars to `constraints` and moves the chars that violate them.
+    pub fn set_constraints(&mut self, constraints: &Constraints) {
+        let allowed_slots = constraints
+            .chars()
+            .into_iter()
+            .filter(|c| self.usable_chars.contains_key(c))
+            .map(|c| {
+                let slots = (0..self.layout.len())
+                    .map(|slot| {
+                        let key = self.positions[slot % self.positions.len()];
+                        constraints.allows(c, key, slot / self.positions.len())
+                    })
+                    .collect();
+                (c, slots)
+            })
+            .collect();
+        self.allowed_slots = Arc::new(allowed_slots);
+        self.place();
+    }
+
+    /// Adds the penalties of `soft_constraints` to the cost of the layout.
+    pub fn set_soft_constraints(&mut self, soft_constraints: SoftConstraints) {
+        self.soft_constraints = Arc::new(soft_constraints);
+    }
+
+    /// The weighted penalty of every soft constraint of the layout.
+    pub fn penalties(&self, physical_layout: &PhysicalLayout) -> Vec<(&SoftConstraint, f32)> {
+        self.soft_constraints.penalties(physical_layout, self)
+    }
+
+    /// Adds the cost of relearning the layout from a reference layout to the cost of the layout.
+    pub fn set_learning_cost(&mut self, learning_cost: LearningCost) {
+        self.learning_cost = Some(Arc::new(learning_cost));
+    }
+
+    /// The distance from the reference layout of the learning cost, if it is set.
+    pub fn learning_distance(&self, physical_layout: &PhysicalLayout) -> Option<f32> {
+        self.learning_cost
+            .as_ref()
+            .map(|learning_cost| learning_cost.distance(physical_layout, self))
+    }
+
+    /// The physical key of the slot of `c`, or its dedicated key.
+    pub fn key(&self, c: char) -> Option<usize> {
+        if let Some(key) = self.fixed_keys.get(&c) {
+            return Some(*key);
+        }
+        let index = self.get_char_index(c);
+        (index < self.layout.len()).then(|| self.slot_keys[index])
+    }
+
+    /// Whether the constraints allow `c` on `slot`.
+    pub fn allows(&self, slot: usize, c: Option<char>) -> bool {
+        c.and_then(|c| self.allowed_slots.get(&c))
+            .is_none_or(|slots| slots[slot])
+    }
+
+    /// Whether swapping slots `a` and `b` keeps the constraints satisfied.
+    pub fn can_swap(&self, a: usize, b: usize) -> bool {
+        self.allows(a, self.layout[b]) && self.allows(b, self.layout[a])
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (0..self.layout.len()).all(|slot| self.allows(slot, self.layout[slot]))
+    }
+
+    /// Moves the constrained chars to allowed slots, keeping chars in their slot where possible
+    /// and the order of the other chars.
+    fn place(&mut self) {
+        if self.allowed_slots.is_empty() || self.is_valid() {
+            return;
+        }
+        let constrained: Vec<char> = self
+            .chars()
+            .filter(|c| self.allowed_slots.contains_key(c))
+            .collect();
+        let mut owners: Vec<Option<usize>> = vec![None; self.layout.len()];
+        for i in 0..constrained.len() {
+            let mut visited = vec![false; self.layout.len()];
+            assert!(
+                self.augment(i, &constrained, &mut owners, &mut visited),
+                "Failed to satisfy the layout constraints"
+            );
+        }
+
+        let mut free = self
+            .layout
+            .iter()
+            .copied()
+            .filter(|c| c.is_none_or(|c| !self.allowed_slots.contains_key(&c)));
+        let layout: Vec<Option<char>> = owners
+            .iter()
+            .map(|owner| match owner {
+                Some(i) => Some(constrained[*i]),
+                None => free.next().expect("Slots must outnumber the chars"),
+            })
+            .collect();
+        for (i, c) in layout.iter().enumerate() {
+            if let Some(c) = c {
+                self.usable_chars.insert(*c, i);
+            }
+        }
+        self.layout = layout;
+    }
+
+    /// Finds an allowed slot for `constrained[i]` by an augmenting path, preferring its own slot.
+    fn augment(
+        &self,
+        i: usize,
+        constrained: &[char],
+        owners: &mut [Option<usize>],
+        visited: &mut [bool],
+    ) -> bool {
+        let c = constrained[i];
+        let allowed = &self.allowed_slots[&c];
+        for slot in std::iter::once(self.usable_chars[&c]).chain(0..self.layout.len()) {
+            if !allowed[slot] || visited[slot] {
+                continue;
+            }
+            visited[slot] = true;
+            if owners[slot].is_none_or(|j| self.augment(j, constrained, owners, visited)) {
+                owners[slot] = Some(i);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Types space with `key`, a dedicated key such as a thumb key.
+    pub fn set_space_key(&mut self, key: usize) {
+        assert!(
+            !self.positions.contains(&key),
+            "The space key must not carry chars"
+        );
+        assert!(
+            !self.usable_chars.contains_key(&' '),
+            "Space must not be placed in a slot"
+        );
+        let mut fixed_keys = (*self.fixed_keys).clone();
+        fixed_keys.insert(' ', key);
+        self.fixed_keys = Arc::new(fixed_keys);
+    }
+
+    /// Puts space on the cheapest of `candidates` for `tri_grams`, such as either thumb key, and
+    /// returns it.
+    pub fn choose_space_key(
+        &mut self,
+        physical_layout: &PhysicalLayout,
+        tri_grams: &HashMap<LogicalNGram<3>, f32>,
+        candidates: &[usize],
+    ) -> usize {
+        let mut best: Option<(usize, f32)> = None;
+        for key in candidates {
+            self.set_space_key(*key);
+            let score = self.evaluate(physical_layout, tri_grams);
+            if best.is_none_or(|(_, best_score)| score < best_score) {
+                best = Some((*key, score));
+            }
+        }
+        let (key, _) = best.expect("There must be a candidate space key");
+        self.set_space_key(key);
+        key
+    }
+
+    /// Types the shifted form of the chars on the layout with `shift` held.
+    pub fn set_shift(&mut self, shift: Shift) {
+        assert!(
+            !self.positions.contains(&shift.key),
+            "The shift key must not carry chars"
+        );
+        self.shift = Some(Arc::new(shift));
+    }
+
+    /// The char typed with shift held to type `c`, when `c` is not on the layout itself.
+    fn unshifted(&self, c: char) -> Option<char> {
+        let base = *self.shift.as_ref()?.pairs.get(&c)?;
+        (!self.usable_chars.contains_key(&c) && self.usable_chars.contains_key(&base))
+            .then_some(base)
+    }
+
+    /// The chars placed on the layout and the shifted chars typed with them.
+    pub fn typeable_chars(&self) -> HashSet<char> {
+        let mut chars: HashSet<char> = self.chars().collect();
+        chars.extend(self.fixed_keys.keys());
+        if let Some(shift) = &self.shift {
+            chars.extend(shift.pairs.keys().filter(|c| self.unshifted(**c).is_some()));
+        }
+        chars
+    }
+
+    /// Sets the extra cost of pressing the modifier of `layer`, on top of its keystroke costs.
+    pub fn set_switch_cost(&mut self, layer: usize, cost: f32) {
+        self.switch_costs[layer - 1] = cost;
+    }
+
+    fn switch_cost(&self, c: char, prev: char) -> f32 {
+        match self.pressed_modifier(c, Some(prev)) {
+            Some(_) => self.switch_costs[self.layer_of(c).unwrap() - 1],
+            None => 0.0,
+        }
+    }
+
+    /// The modifier pressed to type `c` after `prev`, if any.
+    fn pressed_modifier(&self, c: char, prev: Option<char>) -> Option<Modifier> {
+        let layer = self.layer_of(c)?;
+        if layer == 0 {
+            return None;
+        }
+        let modifier = self.modifiers[layer - 1];
+        match modifier {
+            Modifier::Held(_) if prev.and_then(|p| self.layer_of(p)) == Some(layer) => None,
+            _ => Some(modifier),
+        }
+    }
+
+    fn layer_of(&self, c: char) -> Option<usize> {
+        let index = self.get_char_index(c);
+        (index < self.layout.len()).then(|| index / self.positions.len())
+    }
+
+    /// The chars placed on the layout.
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.layout.iter().flatten().copied()
+    }
+
+    pub fn shuffle(&mut self, rng: &mut fastrand::Rng) {
+        rng.shuffle(&mut self.layout);
+        for (i, c) in self.layout.iter().enumerate() {
+            if let Some(c) = c {
+                self.usable_chars.insert(*c, i);
+            }
+        }
+        self.place();
+    }
+
+    /// The chars of `layer` on the main rows in physical key order; modifier keys and empty slots
+    /// are shown as `□`.
+    pub fn layer(&self, layer: usize) -> Vec<char> {
+        let offset = layer * self.positions.len();
+        let mut chars = Vec::new();
+        for (i, key) in self.positions.iter().enumerate() {
+            if *key >= NUM_ROWS * NUM_COLS {
+                break;
+            }
+            while chars.len() < *key {
+                chars.push('□');
+            }
+            chars.push(self.layout[offset + i].unwrap_or('□'));
+        }
+        chars
+    }
+
+    /// The chars of `layer` placed on combos, with the keys of each combo.
+    pub fn combos<'a>(
+        &self,
+        physical_layout: &'a PhysicalLayout,
+        layer: usize,
+    ) -> Vec<(&'a [usize], char)> {
+        let offset = layer * self.positions.len();
+        self.positions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, key)| {
+                let keys = physical_layout.chord_keys(*key)?;
+                self.layout[offset + i].map(|c| (keys, c))
             })
-            .sum();
-        cost
+            .collect()
+    }
+
+    /// The slot of main-row `key` on `layer`.
+    pub fn grid_slot(&self, layer: usize, key: usize) -> Option<usize> {
+        let index = self.positions.iter().position(|position| *position == key)?;
+        (key < NUM_ROWS * NUM_COLS && layer < self.num_layers())
+            .then(|| layer * self.positions.len() + index)
+    }
+
+    pub fn num_layers(&self) -> usize {
+        self.modifiers.len() + 1
     }
 
     pub fn swap(&mut self, a: usize, b: usize) {
-        self.usable_chars.insert(self.layout[a], b);
-        self.usable_chars.insert(self.layout[b], a);
+        if let Some(c) = self.layout[a] {
+            self.usable_chars.insert(c, b);
+        }
+        if let Some(c) = self.layout[b] {
+            self.usable_chars.insert(c, a);
+        }
         self.layout.swap(a, b);
     }
 
@@ -61,12 +583,19 @@ impl LogicalLayout {
         *self.usable_chars.get(&c).unwrap_or(&self.layout.len())
     }
 
-    pub fn get(&self, index: usize) -> char {
+    pub fn get(&self, index: usize) -> Option<char> {
         self.layout[index]
     }
 
-    pub fn set(&mut self, index: usize, c: char) {
-        self.usable_chars.insert(c, index);
+    pub fn set(&mut self, index: usize, c: Option<char>) {
+        if let Some(old) = self.layout[index] {
+            if self.usable_chars.get(&old) == Some(&index) {
+                self.usable_chars.remove(&old);
+            }
+        }
+        if let Some(c) = c {
+            self.usable_chars.insert(c, index);
+        }
         self.layout[index] = c;
     }
 
@@ -74,11 +603,20 @@ impl LogicalLayout {
         self.layout.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.layout.is_empty()
+    }
+
     pub fn char_nums(&self) -> usize {
         self.usable_chars.len()
     }
 
-    pub fn output(self) -> Vec<char> {
+    /// The char of every slot.
+    pub fn slots(&self) -> &[Option<char>] {
+        &self.layout
+    }
+
+    pub fn output(self) -> Vec<Option<char>> {
         self.layout
     }
 }
@@ -107,4 +645,235 @@ mod tests {
         assert_eq!(logical_layout.len(), 30);
         assert_eq!(logical_layout.char_nums(), 3);
     }
+
+    #[test]
+    fn test_off_layout() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+        let logical_layout =
+            LogicalLayout::from_usable_chars(&physical_layout, vec!['a', 'b', 'c']);
+        assert_eq!(logical_layout.get(3), None);
+        assert_eq!(logical_layout.layer(0)[3], '□');
+
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'b', ' ']), 0.5),
+            (LogicalNGram::new(['b', ' ', 'c']), 0.5),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            logical_layout.evaluate(&physical_layout, &tri_grams),
+            0.5 * 100.0
+        );
+        physical_layout.set_off_layout_cost(10.0);
+        assert_eq!(
+            logical_layout.evaluate(&physical_layout, &tri_grams),
+            0.5 * 10.0
+        );
+
+        let mono_grams: HashMap<LogicalNGram<1>, f32> = [
+            (LogicalNGram::new(['a']), 0.6),
+            (LogicalNGram::new([' ']), 0.3),
+            (LogicalNGram::new(['d']), 0.1),
+        ]
+        .into_iter()
+        .collect();
+        assert!((logical_layout.off_layout_mass(&mono_grams) - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_space_key() {
+        let mut physical_layout = sample_physical_layout();
+        let left_thumb = physical_layout.add_thumb_key(4, 1.0);
+        let right_thumb = physical_layout.add_thumb_key(5, 1.0);
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = ('a'..='z').collect();
+        let mut logical_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        assert_eq!(logical_layout.len(), 30);
+        logical_layout.set_space_key(left_thumb);
+        assert!(logical_layout.typeable_chars().contains(&' '));
+
+        let mut keys = Vec::new();
+        logical_layout.keystrokes('a', None, &mut keys);
+        logical_layout.keystrokes(' ', Some('a'), &mut keys);
+        logical_layout.keystrokes('f', Some(' '), &mut keys);
+        assert_eq!(keys, vec![0, left_thumb, 5]);
+
+        // words ending on the left hand alternate better with the right thumb
+        let tri_grams: HashMap<LogicalNGram<3>, f32> = [
+            (LogicalNGram::new(['a', 'c', ' ']), 0.5),
+            (LogicalNGram::new(['c', ' ', 'b']), 0.5),
+        ]
+        .into_iter()
+        .collect();
+        let space_key = logical_layout.choose_space_key(
+            &physical_layout,
+            &tri_grams,
+            &[left_thumb, right_thumb],
+        );
+        assert_eq!(space_key, right_thumb);
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+        let mut chars: Vec<char> = "あいうえおかきくけこさしすせそたちつてとなにぬねのはひふへほ"
+            .chars()
+            .collect();
+        chars.truncate(28);
+        chars.extend("がぎ".chars());
+        let logical_layout = LogicalLayout::with_layers(
+            &physical_layout,
+            chars,
+            vec![Modifier::Held(20), Modifier::Prefix(29)],
+        );
+        assert_eq!(logical_layout.len(), 28 * 3);
+        assert_eq!(logical_layout.layer(0)[20], '□');
+        assert_eq!(logical_layout.layer(1)[0], 'が');
+
+        let mut keys = Vec::new();
+        logical_layout.keystrokes('あ', None, &mut keys);
+        logical_layout.keystrokes('が', Some('あ'), &mut keys);
+        logical_layout.keystrokes('ぎ', Some('が'), &mut keys);
+        assert_eq!(keys, vec![0, 20, 0, 1]);
+
+        let tri_grams: HashMap<LogicalNGram<3>, f32> =
+            [(LogicalNGram::new(['あ', 'が', 'ぎ']), 1.0)].into_iter().collect();
+        let stroke_cost = physical_layout.get_tri_gram_cost(&PhysicalNGram::new([20, 0, 1]));
+        assert_eq!(logical_layout.evaluate(&physical_layout, &tri_grams), stroke_cost);
+
+        // ぎ follows が on the held layer, so only a change of layer costs a switch
+        let mut logical_layout = logical_layout;
+        logical_layout.set_switch_cost(1, 0.5);
+        assert_eq!(logical_layout.evaluate(&physical_layout, &tri_grams), stroke_cost);
+        let tri_grams: HashMap<LogicalNGram<3>, f32> =
+            [(LogicalNGram::new(['あ', 'あ', 'が']), 1.0)].into_iter().collect();
+        let stroke_cost = physical_layout.get_tri_gram_cost(&PhysicalNGram::new([0, 0, 20]))
+            + physical_layout.get_tri_gram_cost(&PhysicalNGram::new([0, 20, 0]));
+        assert_eq!(
+            logical_layout.evaluate(&physical_layout, &tri_grams),
+            stroke_cost + 0.5
+        );
+    }
+
+    #[test]
+    fn test_combos() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.add_adjacent_combos(1.0);
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = ('a'..='z').chain(".,;/-'[]=`!?".chars()).collect();
+        let logical_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        assert_eq!(logical_layout.len(), 54);
+        assert_eq!(logical_layout.layer(0).len(), 30);
+
+        let combos = logical_layout.combos(&physical_layout, 0);
+        assert_eq!(combos.len(), 8);
+        assert_eq!(combos[0], (&[0, 1][..], '-'));
+
+        let mut keys = Vec::new();
+        logical_layout.keystrokes('-', None, &mut keys);
+        assert_eq!(keys, vec![physical_layout.chord(&[0, 1]).unwrap()]);
+    }
+
+    #[test]
+    fn test_magic_keys() {
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.calculate_tri_gram_cost();
+
+        let mut chars: Vec<char> = ('a'..='z').chain(['.', ',']).collect();
+        chars.extend([REPEAT_KEY, MAGIC_KEY]);
+        let mut logical_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        logical_layout.set_magic_rules([('e', 'o')].into_iter().collect());
+        assert_eq!(
+            logical_layout.rewrite(&LogicalNGram::new(['l', 'l', 'l'])),
+            ['l', REPEAT_KEY, REPEAT_KEY]
+        );
+        assert_eq!(
+            logical_layout.rewrite(&LogicalNGram::new(['e', 'o', 'e'])),
+            ['e', MAGIC_KEY, 'e']
+        );
+
+        let tri_grams: HashMap<LogicalNGram<3>, f32> =
+            [(LogicalNGram::new(['a', 'l', 'l']), 1.0)].into_iter().collect();
+        let repeat = physical_layout.get_tri_gram_cost(&PhysicalNGram::new([0, 11, 28]));
+        assert_eq!(logical_layout.evaluate(&physical_layout, &tri_grams), repeat);
+    }
+
+    #[test]
+    fn test_shift() {
+        let mut physical_layout = sample_physical_layout();
+        let shift = physical_layout.add_key((2, 0), F::P, 3.0);
+        physical_layout.calculate_tri_gram_cost();
+
+        let chars: Vec<char> = ('a'..='z').chain(['.', ',', ';', '/']).collect();
+        let mut logical_layout = LogicalLayout::from_usable_chars(&physical_layout, chars);
+        logical_layout.set_shift(Shift::us(shift));
+        assert!(logical_layout.typeable_chars().contains(&'A'));
+        assert!(logical_layout.typeable_chars().contains(&':'));
+        assert!(!logical_layout.typeable_chars().contains(&'!'));
+
+        let mut keys = Vec::new();
+        logical_layout.keystrokes('a', None, &mut keys);
+        logical_layout.keystrokes('B', Some('a'), &mut keys);
+        logical_layout.keystrokes('C', Some('B'), &mut keys);
+        assert_eq!(keys, vec![0, shift, 1, 2]);
+
+        // a capital after a lowercase char costs the shift press
+        let lower: HashMap<LogicalNGram<3>, f32> =
+            [(LogicalNGram::new(['a', 'a', 'b']), 1.0)].into_iter().collect();
+        let upper: HashMap<LogicalNGram<3>, f32> =
+            [(LogicalNGram::new(['a', 'a', 'B']), 1.0)].into_iter().collect();
+        assert!(
+            logical_layout.evaluate(&physical_layout, &upper)
+                > logical_layout.evaluate(&physical_layout, &lower)
+        );
+    }
+
+    #[test]
+    fn test_thumb_shift() {
+        let mut physical_layout = sample_physical_layout();
+        let left_thumb = physical_layout.add_thumb_key(4, 1.0);
+        let right_thumb = physical_layout.add_thumb_key(5, 1.0);
+        physical_layout.add_thumb_chords(left_thumb, 0.5);
+        physical_layout.add_thumb_chords(right_thumb, 0.5);
+
+        let chars: Vec<char> = ('a'..='z').chain(['.', ',', ';', '/', 'ア', 'イ']).collect();
+        let logical_layout = LogicalLayout::with_layers(
+            &physical_layout,
+            chars,
+            vec![
+                Modifier::Simultaneous(left_thumb),
+                Modifier::Simultaneous(right_thumb),
+            ],
+        );
+        assert_eq!(logical_layout.len(), 90);
+
+        let mut keys = Vec::new();
+        logical_layout.keystrokes('a', None, &mut keys);
+        logical_layout.keystrokes('ア', Some('a'), &mut keys);
+        logical_layout.keystrokes('イ', Some('ア'), &mut keys);
+        assert_eq!(
+            keys,
+            vec![
+                0,
+                physical_layout.chord(&[left_thumb, 0]).unwrap(),
+                physical_layout.chord(&[1, left_thumb]).unwrap(),
+            ]
+        );
+
+        // combos get a thumb-shift slot too
+        let mut physical_layout = sample_physical_layout();
+        physical_layout.add_adjacent_combos(1.0);
+        let thumb = physical_layout.add_thumb_key(4, 1.0);
+        physical_layout.add_thumb_chords(thumb, 0.5);
+        let chars: Vec<char> = ('a'..='z').collect();
+        let modifiers = vec![Modifier::Simultaneous(thumb)];
+        let mut logical_layout = LogicalLayout::with_layers(&physical_layout, chars, modifiers);
+        assert_eq!(logical_layout.len(), 108);
+        logical_layout.swap(0, 54 + 30);
+        assert_eq!(logical_layout.key('a'), physical_layout.chord(&[thumb, 0, 1]));
+    }
 }
@@ -1,6 +1,7 @@
 pub const NUM_ROWS: usize = 3;
 pub const NUM_COLS: usize = 10;
 
+use rayon::prelude::*;
 use std::cmp::max;
 use std::collections::HashMap;
 
@@ -9,12 +10,27 @@ use crate::n_gram::PhysicalNGram;
 use crate::keyboard_layout::Finger;
 
 
+/// A key outside the main rows: a thumb key below them, or a chord of keys pressed together.
+#[derive(Debug, Clone)]
+struct ExtraKey {
+    coord: (usize, usize),
+    finger: Finger,
+    cost: f32,
+    /// The keys pressed together, empty for thumb keys.
+    chord: Vec<usize>,
+}
+
 #[derive(Debug)]
 pub struct PhysicalLayout {
     cost_matrix: [[f32; NUM_COLS]; NUM_ROWS],
     finger_matrix: [[Finger; NUM_COLS]; NUM_ROWS],
     mapping: [(usize, usize); NUM_COLS * NUM_ROWS],
-    tri_gram_cost: HashMap<PhysicalNGram<3>, f32>,
+    extra_keys: Vec<ExtraKey>,
+    chords: HashMap<Vec<usize>, usize>,
+    tri_gram_cost: Vec<f32>,
+    /// Cost of typing a char that is not on the layout, and position cost of an unknown key.
+    /// Defaults to 100.0, far above any key cost, in place of the 5.0 unknown keys used to cost.
+    off_layout_cost: f32,
 }
 
 impl PhysicalLayout {
@@ -26,28 +42,151 @@ impl PhysicalLayout {
             }
         }
 
-        let tri_gram_cost = HashMap::new();
-
         Ok(PhysicalLayout {
             cost_matrix,
             finger_matrix,
             mapping,
-            tri_gram_cost,
+            extra_keys: Vec::new(),
+            chords: HashMap::new(),
+            off_layout_cost: 100.0,
+            tri_gram_cost: Vec::new(),
         })
     }
 
-    pub fn calculate_tri_gram_cost(&mut self) {
-        let num_keys = NUM_COLS * NUM_ROWS;
-        for k1 in 0..num_keys {
-            for k2 in 0..num_keys {
-                for k3 in 0..num_keys {
-                    self.tri_gram_cost.insert(
-                        PhysicalNGram::new([k1, k2, k3]),
-                        self.stroke_cost(PhysicalNGram::new([k1, k2, k3])),
-                    );
+    /// Adds a key outside the main rows, such as a shift key, and returns its index.
+    ///
+    /// `coord` only decides the hand and the row and column distances to the other keys.
+    pub fn add_key(&mut self, coord: (usize, usize), finger: Finger, cost: f32) -> usize {
+        self.extra_keys.push(ExtraKey {
+            coord,
+            finger,
+            cost,
+            chord: Vec::new(),
+        });
+        self.len() - 1
+    }
+
+    /// Adds a thumb key below column `col` and returns its index.
+    pub fn add_thumb_key(&mut self, col: usize, cost: f32) -> usize {
+        self.add_key((NUM_ROWS, col), Finger::T, cost)
+    }
+
+    /// Adds a chord of `keys` pressed simultaneously and returns its index.
+    ///
+    /// The chord is typed with the fingers of all its keys and is located at its last key that is
+    /// not a thumb key.
+    pub fn add_chord(&mut self, keys: &[usize], cost: f32) -> usize {
+        let located = keys
+            .iter()
+            .rev()
+            .find(|key| !self.is_thumb(**key))
+            .or(keys.last())
+            .expect("A chord needs at least one key");
+        let coord = self.coord(*located).expect("Unknown chord key");
+        let finger = keys
+            .iter()
+            .filter_map(|key| self.finger(*key))
+            .fold(Finger::empty(), |a, b| a | b);
+        let mut chord = keys.to_vec();
+        chord.sort_unstable();
+        self.extra_keys.push(ExtraKey {
+            coord,
+            finger,
+            cost,
+            chord: chord.clone(),
+        });
+        self.chords.insert(chord, self.len() - 1);
+        self.len() - 1
+    }
+
+    /// Adds a chord of `thumb` with every key of the main rows and every combo added so far,
+    /// costing the key's own cost plus `extra_cost`, as used by thumb-shift layouts. Add combos
+    /// first so that a thumb-shift layer also covers them.
+    pub fn add_thumb_chords(&mut self, thumb: usize, extra_cost: f32) {
+        for key in self.positions() {
+            let mut keys = vec![thumb];
+            keys.extend(self.chord_keys(key).unwrap_or(&[key]));
+            let cost = self.position_cost(key) + extra_cost;
+            self.add_chord(&keys, cost);
+        }
+    }
+
+    /// Adds a combo of every two horizontally adjacent keys of the same hand, costing the higher
+    /// cost of the two plus `extra_cost`, and returns their indices.
+    pub fn add_adjacent_combos(&mut self, extra_cost: f32) -> Vec<usize> {
+        let mut combos = Vec::new();
+        for row in 0..NUM_ROWS {
+            for col in 0..NUM_COLS - 1 {
+                if col + 1 == NUM_COLS / 2 {
+                    continue;
                 }
+                let keys = [row * NUM_COLS + col, row * NUM_COLS + col + 1];
+                let cost = self.position_cost(keys[0]).max(self.position_cost(keys[1])) + extra_cost;
+                combos.push(self.add_chord(&keys, cost));
             }
         }
+        combos
+    }
+
+    /// The keys pressed together for `key`, if it is a chord.
+    pub fn chord_keys(&self, key: usize) -> Option<&[usize]> {
+        key.checked_sub(self.mapping.len())
+            .and_then(|i| self.extra_keys.get(i))
+            .map(|extra| extra.chord.as_slice())
+            .filter(|chord| !chord.is_empty())
+    }
+
+    /// The index of the chord of `keys`, if it was added.
+    pub fn chord(&self, keys: &[usize]) -> Option<usize> {
+        let mut chord = keys.to_vec();
+        chord.sort_unstable();
+        self.chords.get(&chord).copied()
+    }
+
+    /// Keys that can carry a char: the main rows and chords of keys on the main rows.
+    pub fn positions(&self) -> Vec<usize> {
+        (0..self.len())
+            .filter(|key| match key.checked_sub(self.mapping.len()) {
+                None => true,
+                Some(i) => {
+                    let chord = &self.extra_keys[i].chord;
+                    !chord.is_empty() && chord.iter().all(|k| *k < self.mapping.len())
+                }
+            })
+            .collect()
+    }
+
+    /// Thumb keys that are not part of a chord, the candidates for a space key.
+    pub fn thumb_keys(&self) -> Vec<usize> {
+        (self.mapping.len()..self.len())
+            .filter(|key| self.is_thumb(*key))
+            .collect()
+    }
+
+    pub fn is_thumb(&self, key: usize) -> bool {
+        key.checked_sub(self.mapping.len())
+            .and_then(|i| self.extra_keys.get(i))
+            .is_some_and(|extra| extra.chord.is_empty() && extra.finger.contains(Finger::T))
+    }
+
+    pub fn set_off_layout_cost(&mut self, cost: f32) {
+        self.off_layout_cost = cost;
+    }
+
+    pub fn off_layout_cost(&self) -> f32 {
+        self.off_layout_cost
+    }
+
+    /// Calculates the cost of every tri-gram of keys. Call it again after adding keys.
+    pub fn calculate_tri_gram_cost(&mut self) {
+        let num_keys = self.len();
+        self.tri_gram_cost = (0..num_keys * num_keys * num_keys)
+            .into_par_iter()
+            .map(|i| {
+                let (k1, k2, k3) = (i / (num_keys * num_keys), i / num_keys % num_keys, i % num_keys);
+                self.stroke_cost(PhysicalNGram::new([k1, k2, k3]))
+            })
+            .collect();
     }
 
     fn position_cost(&self, idx: usize) -> f32 {
@@ -55,7 +194,20 @@ impl PhysicalLayout {
             Some((row, col)) => {
                 self.cost_matrix[*row][*col]
             }
-            None => 5.0, // 未知の文字
+            None => match idx.checked_sub(self.mapping.len()).and_then(|i| self.extra_keys.get(i)) {
+                Some(extra) => extra.cost,
+                None => self.off_layout_cost, // 未知の文字
+            },
+        }
+    }
+
+    pub(crate) fn finger(&self, idx: usize) -> Option<Finger> {
+        match self.mapping.get(idx) {
+            Some((row, col)) => Some(self.finger_matrix[*row][*col]),
+            None => idx
+                .checked_sub(self.mapping.len())
+                .and_then(|i| self.extra_keys.get(i))
+                .map(|extra| extra.finger),
         }
     }
 
@@ -68,8 +220,12 @@ impl PhysicalLayout {
             Some(coord) => coord,
             None => return 5.0,
         };
-        let overlap = Self::has_overlap(&[self.finger_matrix[row1][col1], self.finger_matrix[row2][col2]]);
+        let overlap = Self::has_overlap(&[self.finger(key1).unwrap(), self.finger(key2).unwrap()]);
         let same_finger: i32 = if overlap { 8 } else { 0 };
+        // thumbs move independently of the other fingers
+        if self.is_thumb(key1) || self.is_thumb(key2) {
+            return same_finger as f32;
+        }
         let same_column: i32 = if col1 == col2 { 8 } else { 0 };
         let col_diff = max(0, (col1 as i32 - col2 as i32).abs() - 2);
         let row_diff = max(0, (row1 as i32 - row2 as i32).abs() - 1);
@@ -91,11 +247,15 @@ impl PhysicalLayout {
         };
 
         let overlap = Self::has_overlap(&[
-            self.finger_matrix[row1][col1],
-            self.finger_matrix[row2][col2],
-            self.finger_matrix[row3][col3]
+            self.finger(key1).unwrap(),
+            self.finger(key2).unwrap(),
+            self.finger(key3).unwrap()
         ]);
         let same_finger: i32 = if overlap { 8 } else { 0 };
+        // a thumb key is never part of a roll, but its row and column do not matter
+        if self.is_thumb(key1) || self.is_thumb(key2) || self.is_thumb(key3) {
+            return (same_finger + 8) as f32;
+        }
         let same_column: i32 = if col1 == col2 && col2 == col3 { 8 } else { 0 };
         let not_roll_penalty = if (col1 <= col2 && col2 <= col3) && (col1 >= col2 && col2 >= col3) { 0 } else { 8 };
         let row_diff = max(0, (row1 as i32 - row2 as i32).abs() - 1) +
@@ -141,18 +301,33 @@ impl PhysicalLayout {
     }
 
     pub fn len(&self) -> usize {
-        self.mapping.len()
+        self.mapping.len() + self.extra_keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mapping.is_empty()
     }
 
     pub fn get_tri_gram_cost(&self, n_gram: &PhysicalNGram<3>) -> f32 {
+        let num_keys = self.len();
+        if (0..3).any(|i| n_gram.get(i) >= num_keys) {
+            panic!("Failed to get tri gram cost");
+        }
+        let index = (n_gram.get(0) * num_keys + n_gram.get(1)) * num_keys + n_gram.get(2);
         *self
             .tri_gram_cost
-            .get(n_gram)
+            .get(index)
             .expect("Failed to get tri gram cost")
     }
 
-    fn coord(&self, index: usize) -> Option<(usize, usize)> {
-        self.mapping.get(index).copied()
+    pub(crate) fn coord(&self, index: usize) -> Option<(usize, usize)> {
+        match self.mapping.get(index) {
+            Some(coord) => Some(*coord),
+            None => index
+                .checked_sub(self.mapping.len())
+                .and_then(|i| self.extra_keys.get(i))
+                .map(|extra| extra.coord),
+        }
     }
 
     fn has_overlap(fingers: &[Finger]) -> bool {
@@ -164,7 +339,7 @@ impl PhysicalLayout {
             })
     }
 
-    fn hand(&self, index: usize) -> Hand {
+    pub(crate) fn hand(&self, index: usize) -> Hand {
         match self.coord(index) {
             Some((_, col)) => {
                 if col < NUM_COLS / 2 {
@@ -187,8 +362,7 @@ impl PhysicalLayout {
                 print!("{} ", key);
                 if (i + 1) * (j + 1) == self.cost_matrix[0].len() * self.cost_matrix.len() {
                     println!();
-                    std::iter::repeat("--")
-                        .take(self.cost_matrix[0].len() + 1)
+                    std::iter::repeat_n("--", self.cost_matrix[0].len() + 1)
                         .for_each(|c| {
                             print!("{}", c);
                         });
@@ -199,42 +373,6 @@ impl PhysicalLayout {
     }
 }
 
-fn get_left_grams() -> Vec<PhysicalNGram<3>> {
-    let mut keys = Vec::new();
-    for i in 0..NUM_ROWS {
-        for j in 0..NUM_COLS / 2 {
-            keys.push(i * NUM_COLS + j);
-        }
-    }
-    let mut grams = Vec::new();
-    for k1 in keys.iter() {
-        for k2 in keys.iter() {
-            for k3 in keys.iter() {
-                grams.push(PhysicalNGram::new([*k1, *k2, *k3]));
-            }
-        }
-    }
-    grams
-}
-
-fn get_right_grams() -> Vec<PhysicalNGram<3>> {
-    let mut keys = Vec::new();
-    for i in 0..NUM_ROWS {
-        for j in NUM_COLS / 2..NUM_COLS {
-            keys.push(i * NUM_COLS + j);
-        }
-    }
-    let mut grams = Vec::new();
-    for k1 in keys.iter() {
-        for k2 in keys.iter() {
-            for k3 in keys.iter() {
-                grams.push(PhysicalNGram::new([*k1, *k2, *k3]));
-            }
-        }
-    }
-    grams
-}
-
 pub fn get_left_keys() -> Vec<usize> {
     let mut keys = Vec::new();
     for i in 0..NUM_ROWS {
@@ -255,6 +393,23 @@ pub fn get_right_keys() -> Vec<usize> {
     keys
 }
 
+/// The layout shared by the tests: a QWERTY-like cost matrix with the usual finger assignment.
+#[cfg(test)]
+pub(crate) fn sample_physical_layout() -> PhysicalLayout {
+    use crate::keyboard_layout::hand_model::Finger as F;
+    let cost_matrix: [[f32; NUM_COLS]; NUM_ROWS] = [
+        [3.0, 2.4, 2.0, 2.2, 3.2, 3.2, 2.2, 2.0, 2.4, 3.0], // 上段
+        [1.6, 1.3, 1.1, 1.0, 2.9, 2.9, 1.0, 1.1, 1.3, 1.6], // 中段（ホームポジション）
+        [3.2, 2.6, 2.3, 1.6, 3.0, 3.0, 1.6, 2.3, 2.6, 3.2], // 下段
+    ];
+    let finger_table: [[F; NUM_COLS]; NUM_ROWS] = [
+        [F::R, F::R, F::M, F::M, F::I, F::I, F::M, F::M, F::R, F::R],
+        [F::P, F::R, F::M, F::I, F::I, F::I, F::I, F::M, F::R, F::P],
+        [F::P, F::R, F::M, F::I, F::I, F::I, F::I, F::M, F::R, F::P],
+    ];
+    PhysicalLayout::new(cost_matrix, finger_table).unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,4 +431,38 @@ mod tests {
         assert_eq!(physical_layout.position_cost(0), 3.0);
         assert_eq!(physical_layout.position_cost(48), 100.0);
     }
+
+    #[test]
+    fn test_chords() {
+        let mut physical_layout = sample_physical_layout();
+        let thumb = physical_layout.add_thumb_key(4, 1.0);
+        physical_layout.add_thumb_chords(thumb, 0.5);
+        assert_eq!(thumb, 30);
+        assert!(physical_layout.is_thumb(thumb));
+        assert_eq!(physical_layout.thumb_keys(), vec![thumb]);
+        assert_eq!(physical_layout.len(), 61);
+        assert_eq!(physical_layout.positions().len(), 30);
+
+        let chord = physical_layout.chord(&[12, thumb]).unwrap();
+        assert_eq!(physical_layout.position_cost(chord), 1.6);
+        assert_eq!(physical_layout.hand(chord), Hand::Left);
+        // the thumb does not collide with the middle finger of the chord
+        assert_eq!(physical_layout.relative_cost(thumb, 12), 0.0);
+        assert_eq!(physical_layout.relative_cost(chord, 12), 16.0);
+    }
+
+    #[test]
+    fn test_adjacent_combos() {
+        let mut physical_layout = sample_physical_layout();
+        let combos = physical_layout.add_adjacent_combos(1.0);
+        assert_eq!(combos.len(), 24);
+        assert_eq!(physical_layout.positions().len(), 54);
+        assert!(physical_layout.chord(&[14, 15]).is_none());
+
+        let combo = physical_layout.chord(&[12, 11]).unwrap();
+        assert_eq!(physical_layout.chord_keys(combo), Some(&[11, 12][..]));
+        assert_eq!(physical_layout.position_cost(combo), 2.3);
+        // the ring finger of the combo types the next key too
+        assert_eq!(physical_layout.relative_cost(combo, 21), 8.0);
+    }
 }
@@ -1,3 +1,4 @@
 pub mod algorithms;
+pub mod corpus;
 pub mod keyboard_layout;
 pub mod n_gram;
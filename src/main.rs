@@ -1,16 +1,20 @@
 use std::path::Path;
 use std::collections::HashSet;
 
-use keyboard_layout_optimizer::algorithms::Genetic;
 use keyboard_layout_optimizer::keyboard_layout::*;
-use keyboard_layout_optimizer::n_gram::NGramDB;
+use keyboard_layout_optimizer::n_gram::{CountOptions, NGramDB};
 use keyboard_layout_optimizer::keyboard_layout::Finger as F;
 
 fn main() -> Result<(), std::io::Error> {
     let source_paths = vec![Path::new("data/ja.txt"), Path::new("data/en.txt")];
     let db_path = Path::new("data/ja_en.db");
     if !db_path.exists() {
-        let _ = NGramDB::new(&source_paths, db_path).expect("Failed to create NGramDB");
+        let options = CountOptions {
+            progress: true,
+            ..CountOptions::default()
+        };
+        let _ = NGramDB::with_options(&source_paths, db_path, &options)
+            .expect("Failed to create NGramDB");
     }
     let n_gram_db = NGramDB::load(db_path).expect("Failed to load NGramDB");
 
@@ -29,17 +33,12 @@ fn main() -> Result<(), std::io::Error> {
         [F::P, F::R, F::M, F::I, F::I, F::I, F::I, F::M, F::R, F::P],
         [F::P, F::R, F::M, F::I, F::I, F::I, F::I, F::M, F::R, F::P],
     ];
-    let mut physical_layout = PhysicalLayout::new(cost_table, finger_table.clone()).expect("Invalid cost table");
+    let mut physical_layout = PhysicalLayout::new(cost_table, finger_table).expect("Invalid cost table");
     physical_layout.calculate_tri_gram_cost();
     let mut normal_physical_layout =
         PhysicalLayout::new(normal_cost_table, finger_table).expect("Invalid cost table");
     normal_physical_layout.calculate_tri_gram_cost();
 
-    let usable_chars = vec![
-        'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
-        's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '*', ';', '/', '+',
-    ];
-
     let qwerty_layout = vec![
         'q', 'w', 'e', 'r', 't', 'y', 'u', 'i', 'o', 'p', 'a', 's', 'd', 'f', 'g', 'h', 'j', 'k',
         'l', ';', 'z', 'x', 'c', 'v', 'b', 'n', 'm', ',', '.', '/',
@@ -87,9 +86,13 @@ fn main() -> Result<(), std::io::Error> {
     let custom = LogicalLayout::from_usable_chars(&physical_layout, custom_layout.clone());
     let score = custom.evaluate(&physical_layout, &tri_grams);
     println!("custom score: {}", score);
-    physical_layout.print(&custom.output());
+    physical_layout.print(&custom.layer(0));
 
-    // let algorithm = Genetic::new(32, 16);
+    // let usable_chars = vec![
+    //     'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
+    //     's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '*', ';', '/', '+',
+    // ];
+    // let algorithm = keyboard_layout_optimizer::algorithms::Genetic::new(32, 16);
 
     // algorithm.optimize(&physical_layout, &usable_chars, &n_gram_db, 40000, true, 3000);
 
@@ -1,9 +1,12 @@
+mod counter;
+
 use rusqlite::{params, Connection, Result};
 use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
-use std::fs;
 use std::path::Path;
 
+pub use counter::CountOptions;
+
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub struct PhysicalNGram<const N: usize>([usize; N]);
 
@@ -44,9 +47,14 @@ impl<const N: usize> LogicalNGram<N> {
 }
 
 fn generate_n_grams(text: &str, n: usize) -> Vec<&str> {
-    text.as_bytes()
-        .windows(n)
-        .map(|w| std::str::from_utf8(w).unwrap())
+    let boundaries: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect();
+    boundaries
+        .windows(n + 1)
+        .map(|w| &text[w[0]..w[n]])
         .collect()
 }
 
@@ -56,6 +64,14 @@ pub struct NGramDB {
 
 impl NGramDB {
     pub fn new<P: AsRef<Path>>(source_paths: &[P], db_path: P) -> Result<Self> {
+        Self::with_options(source_paths, db_path, &CountOptions::default())
+    }
+
+    pub fn with_options<P: AsRef<Path>>(
+        source_paths: &[P],
+        db_path: P,
+        options: &CountOptions,
+    ) -> Result<Self> {
         let mut conn = Connection::open(db_path).expect("Failed to open database");
 
         conn.execute(
@@ -68,29 +84,33 @@ impl NGramDB {
             [],
         )
         .expect("Failed to create table");
-
-        let tx = conn.transaction().expect("Failed to create transaction");
-
-        let mut n_gram_counts: HashMap<String, usize> = HashMap::new();
-
-        let n = 3;
-        for source_path in source_paths {
-            let text = fs::read_to_string(source_path).expect("Failed to read file");
-            let n_grams = generate_n_grams(&text, n);
-            for n_gram in &n_grams {
-                *n_gram_counts.entry(n_gram.to_string()).or_insert(0) += 1;
-            }
-        }
-
-        for (n_gram_str, count) in n_gram_counts {
-            tx.execute(
-                "INSERT INTO n_grams (n, n_gram, count) VALUES (?1, ?2, ?3)",
-                params![n as u8, n_gram_str, count],
+        let indexed: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master
+                                WHERE type = 'index' AND name = 'n_grams_n_n_gram')",
+                [],
+                |row| row.get(0),
+            )
+            .expect("Failed to look up index");
+        if !indexed {
+            // databases written before the index may hold an n-gram more than once
+            conn.execute_batch(
+                "BEGIN;
+                 UPDATE n_grams SET count = (
+                     SELECT SUM(count) FROM n_grams AS same
+                     WHERE same.n = n_grams.n AND same.n_gram = n_grams.n_gram
+                 )
+                 WHERE id IN (SELECT MIN(id) FROM n_grams GROUP BY n, n_gram HAVING COUNT(*) > 1);
+                 DELETE FROM n_grams
+                 WHERE id NOT IN (SELECT MIN(id) FROM n_grams GROUP BY n, n_gram);
+                 CREATE UNIQUE INDEX n_grams_n_n_gram ON n_grams (n, n_gram);
+                 COMMIT;",
             )
-            .expect("Failed to insert n-gram");
+            .expect("Failed to create index");
         }
 
-        tx.commit().expect("Failed to commit transaction");
+        counter::count_sources(&mut conn, source_paths, options)
+            .expect("Failed to insert n-grams");
 
         Ok(NGramDB { conn })
     }
@@ -108,7 +128,7 @@ impl NGramDB {
         let n_grams_iter = stmt
             .query_map(params![1_i32], |row| {
                 let n_gram: String = row.get(0).expect("Failed to get n-gram");
-                let count: u32 = row.get(1).expect("Failed to get count");
+                let count: i64 = row.get(1).expect("Failed to get count");
                 Ok((
                     LogicalNGram::new(n_gram.chars().collect::<Vec<char>>().try_into().unwrap()),
                     count as f32,
@@ -139,7 +159,7 @@ impl NGramDB {
         let n_grams_iter = stmt
             .query_map(params![3_i32], |row| {
                 let n_gram: String = row.get(0).expect("Failed to get n-gram");
-                let count: u32 = row.get(1).expect("Failed to get frequency");
+                let count: i64 = row.get(1).expect("Failed to get frequency");
                 Ok((
                     LogicalNGram::new(n_gram.chars().collect::<Vec<char>>().try_into().unwrap()),
                     count as f32,
@@ -183,6 +203,10 @@ mod tests {
         let two_grams = generate_n_grams(text, 2);
         assert_eq!(two_grams.len(), 4);
         assert_eq!(two_grams, vec!["ab", "bc", "cd", "de"]);
+
+        // マルチバイト文字
+        let kana_grams = generate_n_grams("かなa", 2);
+        assert_eq!(kana_grams, vec!["かな", "なa"]);
     }
 
     #[test]
@@ -238,4 +262,43 @@ mod tests {
         fs::remove_file(file_path).expect("Failed to remove test file");
         fs::remove_file(db_path).expect("Failed to remove test database");
     }
+
+    #[test]
+    fn test_duplicate_rows() {
+        let file_path = "test_duplicates.txt";
+        let db_path = "test_duplicates.db";
+        let _ = fs::remove_file(db_path);
+
+        // 索引のない古いデータベースに重複した行を作成
+        let conn = Connection::open(db_path).expect("Failed to open database");
+        conn.execute_batch(
+            "CREATE TABLE n_grams (
+                 id INTEGER PRIMARY KEY,
+                 n INTEGER NOT NULL,
+                 n_gram TEXT NOT NULL,
+                 count INTEGER NOT NULL
+             );
+             INSERT INTO n_grams (n, n_gram, count) VALUES (3, 'abc', 2), (3, 'abc', 2), (3, 'bca', 1);",
+        )
+        .expect("Failed to create old database");
+        drop(conn);
+
+        fs::write(file_path, "abc").expect("Failed to write test file");
+        let n_gram_db = NGramDB::new(&[file_path], db_path).expect("Failed to create NGramDB");
+        let count = |n_gram: &str| -> (i64, i64) {
+            n_gram_db
+                .conn
+                .query_row(
+                    "SELECT COUNT(*), SUM(count) FROM n_grams WHERE n = 3 AND n_gram = ?1",
+                    [n_gram],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .expect("Failed to count rows")
+        };
+        assert_eq!(count("abc"), (1, 5));
+        assert_eq!(count("bca"), (1, 1));
+
+        fs::remove_file(file_path).expect("Failed to remove test file");
+        fs::remove_file(db_path).expect("Failed to remove test database");
+    }
 }
@@ -0,0 +1,326 @@
+use rayon::prelude::*;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::generate_n_grams;
+use crate::corpus::{NormalizeState, Normalizer};
+
+/// Options for streaming n-gram counting.
+#[derive(Debug, Clone)]
+pub struct CountOptions {
+    /// n-grams of every size in `1..=max_n` are counted.
+    pub max_n: usize,
+    /// Number of bytes read from a source per chunk.
+    pub chunk_size: usize,
+    /// Number of distinct n-grams kept in memory before they are flushed to the database.
+    pub flush_threshold: usize,
+    /// Print the progress of every source while counting.
+    pub progress: bool,
+    /// Normalization applied to every source before its n-grams are generated.
+    pub normalizer: Option<Normalizer>,
+}
+
+impl Default for CountOptions {
+    fn default() -> Self {
+        Self {
+            max_n: 3,
+            chunk_size: 4 * 1024 * 1024,
+            flush_threshold: 4_000_000,
+            progress: false,
+            normalizer: None,
+        }
+    }
+}
+
+/// A piece of text read from a source.
+///
+/// The first `skip` chars are carried over from the previous chunk so that n-grams spanning the
+/// chunk boundary are counted, while n-grams lying entirely within them are not counted twice.
+#[derive(Debug)]
+struct Chunk {
+    text: String,
+    skip: usize,
+}
+
+/// Reads a UTF-8 source in fixed-size chunks that always end at a char boundary.
+struct ChunkReader<'a, R: Read> {
+    reader: R,
+    chunk_size: usize,
+    overlap: usize,
+    pending: Vec<u8>,
+    tail: String,
+    done: bool,
+    normalizer: Option<(&'a Normalizer, NormalizeState)>,
+}
+
+impl<'a, R: Read> ChunkReader<'a, R> {
+    fn new(
+        reader: R,
+        chunk_size: usize,
+        overlap: usize,
+        normalizer: Option<&'a Normalizer>,
+    ) -> Self {
+        Self {
+            reader,
+            chunk_size: chunk_size.max(4),
+            overlap,
+            pending: Vec::new(),
+            tail: String::new(),
+            done: false,
+            normalizer: normalizer.map(|normalizer| (normalizer, NormalizeState::default())),
+        }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<Option<(Chunk, usize)>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let mut buf = std::mem::take(&mut self.pending);
+        let start = buf.len();
+        buf.resize(start + self.chunk_size, 0);
+        let mut filled = start;
+        while filled < buf.len() {
+            let read = self.reader.read(&mut buf[filled..])?;
+            if read == 0 {
+                self.done = true;
+                break;
+            }
+            filled += read;
+        }
+        buf.truncate(filled);
+        let read_bytes = filled - start;
+
+        let valid = match std::str::from_utf8(&buf) {
+            Ok(_) => buf.len(),
+            Err(e) if e.error_len().is_none() && !self.done => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        self.pending = buf.split_off(valid);
+        let mut body = String::from_utf8(buf).expect("Chunk must be valid UTF-8");
+        if let Some((normalizer, state)) = &mut self.normalizer {
+            let mut normalized = String::with_capacity(body.len());
+            normalizer.push_str(state, &body, &mut normalized);
+            if self.done {
+                normalizer.finish(state, &mut normalized);
+            }
+            body = normalized;
+        }
+        if body.is_empty() && self.done {
+            return Ok(None);
+        }
+
+        let skip = self.tail.chars().count();
+        let mut text = std::mem::take(&mut self.tail);
+        text.push_str(&body);
+
+        let tail_start = text
+            .char_indices()
+            .rev()
+            .nth(self.overlap.saturating_sub(1))
+            .map_or(0, |(i, _)| i);
+        if self.overlap > 0 {
+            self.tail = text[tail_start..].to_string();
+        }
+
+        Ok(Some((Chunk { text, skip }, read_bytes)))
+    }
+}
+
+/// In-memory n-gram counts, indexed by `n - 1`.
+#[derive(Debug, Default)]
+struct Counts(Vec<HashMap<String, u64>>);
+
+impl Counts {
+    fn new(max_n: usize) -> Self {
+        Counts((0..max_n).map(|_| HashMap::new()).collect())
+    }
+
+    fn count_chunk(chunk: &Chunk, max_n: usize) -> Self {
+        let mut counts = Self::new(max_n);
+        for n in 1..=max_n {
+            let map = &mut counts.0[n - 1];
+            // Windows ending inside the carried-over prefix were counted with the previous chunk.
+            let already_counted = (chunk.skip + 1).saturating_sub(n);
+            for n_gram in generate_n_grams(&chunk.text, n)
+                .into_iter()
+                .skip(already_counted)
+            {
+                match map.get_mut(n_gram) {
+                    Some(count) => *count += 1,
+                    None => {
+                        map.insert(n_gram.to_string(), 1);
+                    }
+                }
+            }
+        }
+        counts
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        for (map, other_map) in self.0.iter_mut().zip(other.0) {
+            if map.len() < other_map.len() {
+                let smaller = std::mem::replace(map, other_map);
+                for (n_gram, count) in smaller {
+                    *map.entry(n_gram).or_insert(0) += count;
+                }
+            } else {
+                for (n_gram, count) in other_map {
+                    *map.entry(n_gram).or_insert(0) += count;
+                }
+            }
+        }
+        self
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|map| map.len()).sum()
+    }
+
+    fn flush(&mut self, conn: &mut Connection) -> rusqlite::Result<()> {
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO n_grams (n, n_gram, count) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(n, n_gram) DO UPDATE SET count = count + excluded.count",
+            )?;
+            for (i, map) in self.0.iter_mut().enumerate() {
+                for (n_gram, count) in map.drain() {
+                    stmt.execute(params![(i + 1) as u8, n_gram, count as i64])?;
+                }
+            }
+        }
+        tx.commit()
+    }
+}
+
+/// Counts the n-grams of every source in parallel, chunk by chunk, and accumulates them into the
+/// `n_grams` table of `conn`.
+pub(super) fn count_sources<P: AsRef<Path>>(
+    conn: &mut Connection,
+    source_paths: &[P],
+    options: &CountOptions,
+) -> rusqlite::Result<()> {
+    let max_n = options.max_n.max(1);
+    let batch_size = rayon::current_num_threads().max(1);
+    let mut counts = Counts::new(max_n);
+
+    for source_path in source_paths {
+        let source_path = source_path.as_ref();
+        let file = File::open(source_path).expect("Failed to read file");
+        let total_bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut reader = ChunkReader::new(
+            file,
+            options.chunk_size,
+            max_n - 1,
+            options.normalizer.as_ref(),
+        );
+        let mut processed_bytes = 0;
+
+        loop {
+            let mut chunks = Vec::with_capacity(batch_size);
+            while chunks.len() < batch_size {
+                match reader.next_chunk().expect("Failed to read file") {
+                    Some((chunk, read_bytes)) => {
+                        processed_bytes += read_bytes as u64;
+                        chunks.push(chunk);
+                    }
+                    None => break,
+                }
+            }
+            if chunks.is_empty() {
+                break;
+            }
+
+            let partial = chunks
+                .par_iter()
+                .map(|chunk| Counts::count_chunk(chunk, max_n))
+                .reduce(|| Counts::new(max_n), Counts::merge);
+            counts = counts.merge(partial);
+
+            if counts.len() > options.flush_threshold {
+                counts.flush(conn)?;
+            }
+
+            if options.progress {
+                let percent = if total_bytes == 0 {
+                    100.0
+                } else {
+                    processed_bytes as f64 / total_bytes as f64 * 100.0
+                };
+                println!(
+                    "counting {}: {} / {} bytes ({:.1}%)",
+                    source_path.display(),
+                    processed_bytes,
+                    total_bytes,
+                    percent
+                );
+            }
+        }
+    }
+
+    counts.flush(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_in_chunks(
+        text: &str,
+        chunk_size: usize,
+        max_n: usize,
+        normalizer: Option<&Normalizer>,
+    ) -> Counts {
+        let mut reader = ChunkReader::new(text.as_bytes(), chunk_size, max_n - 1, normalizer);
+        let mut counts = Counts::new(max_n);
+        while let Some((chunk, _)) = reader.next_chunk().unwrap() {
+            counts = counts.merge(Counts::count_chunk(&chunk, max_n));
+        }
+        counts
+    }
+
+    #[test]
+    fn test_chunk_boundaries() {
+        let text = "かなabcあいう abcabc えおabc";
+        let expected = count_in_chunks(text, text.len(), 3, None);
+        for chunk_size in 4..text.len() {
+            let counts = count_in_chunks(text, chunk_size, 3, None);
+            for n in 0..3 {
+                assert_eq!(counts.0[n], expected.0[n], "chunk_size: {}", chunk_size);
+            }
+        }
+        assert_eq!(expected.0[2].get("abc"), Some(&4));
+        assert_eq!(expected.0[0].get("あ"), Some(&1));
+    }
+
+    #[test]
+    fn test_normalized_chunks() {
+        let text = "The  QUICK brown\n\nfox,  jumps over   the lazy dog.";
+        let normalizer = Normalizer::ascii_lowercase();
+        let normalized = normalizer.normalize(text);
+        let expected = count_in_chunks(&normalized, normalized.len(), 3, None);
+        for chunk_size in 4..text.len() {
+            let counts = count_in_chunks(text, chunk_size, 3, Some(&normalizer));
+            for n in 0..3 {
+                assert_eq!(counts.0[n], expected.0[n], "chunk_size: {}", chunk_size);
+            }
+        }
+
+        // NFD kana and halfwidth voiced marks compose with the kana before a chunk boundary
+        let text = "か\u{3099}き\u{3099} ｶﾞｷﾞ か\u{3099}";
+        let normalizer = Normalizer::default();
+        let normalized = normalizer.normalize(text);
+        assert_eq!(normalized, "がぎ ガギ が");
+        let expected = count_in_chunks(&normalized, normalized.len(), 3, None);
+        for chunk_size in 4..text.len() {
+            let counts = count_in_chunks(text, chunk_size, 3, Some(&normalizer));
+            for n in 0..3 {
+                assert_eq!(counts.0[n], expected.0[n], "chunk_size: {}", chunk_size);
+            }
+        }
+    }
+}